@@ -1,15 +1,23 @@
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use dotenvy::dotenv;
 use std::env;
-use std::str::FromStr;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use super::model::ModelConfig;
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub cloudinary: CloudinaryConfig,
     pub minio: MinioConfig,
+    pub local: LocalStorageConfig,
     pub model: ModelConfig,
+    pub inference: InferenceConfig,
+    pub processing: ProcessingConfig,
     pub s3: S3Config,
+    /// Uploader used when a request doesn't pick one, the first enabled one when unset
+    pub default_uploader: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -44,72 +52,174 @@ pub struct MinioConfig {
     pub region: String,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum ModelSize {
-    Small,
-    Medium,
-    Large,
+#[derive(Debug, Clone)]
+pub struct LocalStorageConfig {
+    /// Directory the local uploader writes to, local storage is disabled when unset
+    pub directory: Option<String>,
+    /// Path of the route serving stored files
+    pub route: String,
+    /// URL stored files are reachable under, returned as upload URLs
+    pub public_base_url: String,
 }
 
-impl FromStr for ModelSize {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "small" => Ok(ModelSize::Small),
-            "medium" => Ok(ModelSize::Medium),
-            "large" => Ok(ModelSize::Large),
-            _ => Err(anyhow!(
-                "Invalid model size. Valid values are: small, medium, large"
-            )),
-        }
+impl CloudinaryConfig {
+    pub fn is_configured(&self) -> bool {
+        !self.cloud_name.is_empty() && !self.api_key.is_empty() && !self.api_secret.is_empty()
     }
 }
 
-impl ModelSize {
-    pub fn get_model_path(&self) -> String {
-        let model_name = match self {
-            ModelSize::Small => "small",
-            ModelSize::Medium => "medium",
-            ModelSize::Large => "large",
-        };
-        format!("models/{}.onnx", model_name)
+impl S3Config {
+    pub fn is_configured(&self) -> bool {
+        !self.access_key.is_empty() && !self.secret_key.is_empty() && !self.bucket.is_empty()
+    }
+}
+
+impl MinioConfig {
+    pub fn is_configured(&self) -> bool {
+        !self.access_key.is_empty()
+            && !self.secret_key.is_empty()
+            && !self.bucket.is_empty()
+            && !self.endpoint.is_empty()
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ModelConfig {
-    pub size: ModelSize,
-    pub path: String,
+pub struct InferenceConfig {
+    /// Sessions kept per model unless the model sets its own count
+    pub pool_size: usize,
+    /// Threads used within a single operator of a session
+    pub intra_threads: i16,
+    /// Threads used to run independent operators of a session in parallel
+    pub inter_threads: i16,
+    /// Maximum number of callers allowed to wait for a free session
+    pub queue_depth: usize,
+    /// Size of the thread pool running decoding, inference and encoding
+    pub executor_threads: usize,
+    /// How long to wait for more requests before running a partial batch
+    pub batch_window: Duration,
+    /// Largest batch sent to models with a dynamic batch dimension
+    pub max_batch_size: usize,
+    /// Longest side of the input for models with dynamic spatial dimensions
+    pub dynamic_longest_side: usize,
+    /// Both input sides of dynamic models are rounded to a multiple of this
+    pub dynamic_size_multiple: usize,
+}
+
+/// Alpha above which pixels count towards the auto-crop bounds when
+/// `CROP_THRESHOLD` isn't set
+pub const DEFAULT_CROP_THRESHOLD: u8 = 10;
+
+/// Defaults for processing options that requests can override
+#[derive(Debug, Clone)]
+pub struct ProcessingConfig {
+    /// Pad images to the model's input size instead of stretching them
+    pub letterbox: bool,
+    /// Alpha above which pixels count towards the auto-crop bounds
+    pub crop_threshold: u8,
+    /// Subject parts smaller than this many pixels are ignored by auto-crop
+    pub crop_min_area: u32,
+    /// Largest output, in pixels, a request may produce
+    pub max_output_pixels: u64,
 }
 
 impl AppConfig {
     pub fn from_env() -> Result<Self> {
         dotenv().ok();
 
-        let model_size = ModelSize::from_str(
-            &env::var("ONNX_MODEL_SIZE").unwrap_or_else(|_| "medium".to_string()),
-        )?;
+        let server = ServerConfig {
+            host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+            port: env::var("SERVER_PORT")
+                .unwrap_or_else(|_| "8080".to_string())
+                .parse()?,
+        };
 
-        let model_config = ModelConfig {
-            size: model_size,
-            path: model_size.get_model_path(),
+        // Stored files are served by this server unless a public URL is given
+        let local_route = env::var("LOCAL_STORAGE_ROUTE").unwrap_or_else(|_| "/files".to_string());
+        let directory = env::var("LOCAL_STORAGE_DIR").ok();
+        let public_base_url = match env::var("LOCAL_PUBLIC_URL") {
+            Ok(url) => url,
+            // A wildcard bind address isn't something clients can connect to
+            Err(_) if directory.is_some() && is_wildcard_host(&server.host) => bail!(
+                "LOCAL_PUBLIC_URL must be set when local storage is enabled and SERVER_HOST is {}",
+                server.host
+            ),
+            Err(_) => format!("http://{}:{}{}", server.host, server.port, local_route),
+        };
+        let local = LocalStorageConfig {
+            directory,
+            public_base_url,
+            route: local_route,
+        };
+
+        // Without ONNX_POOL_SIZE the CPU cores are split across models that don't set a count
+        let model = ModelConfig::from_env()?;
+        let pool_size = match env::var("ONNX_POOL_SIZE") {
+            Ok(size) => size.parse()?,
+            Err(_) => {
+                let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+                let unsized_models = model
+                    .sources
+                    .iter()
+                    .filter(|s| s.pool_size.is_none())
+                    .count();
+                (cores / unsized_models.max(1)).max(1)
+            }
         };
 
         Ok(Self {
-            server: ServerConfig {
-                host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-                port: env::var("SERVER_PORT")
-                    .unwrap_or_else(|_| "8080".to_string())
+            server,
+            cloudinary: CloudinaryConfig {
+                cloud_name: env::var("CLOUDINARY_CLOUD_NAME").unwrap_or("".to_string()),
+                api_key: env::var("CLOUDINARY_API_KEY").unwrap_or("".to_string()),
+                api_secret: env::var("CLOUDINARY_API_SECRET").unwrap_or("".to_string()),
+                upload_preset: env::var("CLOUDINARY_UPLOAD_PRESET").unwrap_or("".to_string()),
+            },
+            model,
+            inference: InferenceConfig {
+                pool_size,
+                intra_threads: env::var("ONNX_INTRA_THREADS")
+                    .unwrap_or_else(|_| "1".to_string())
+                    .parse()?,
+                inter_threads: env::var("ONNX_INTER_THREADS")
+                    .unwrap_or_else(|_| "1".to_string())
+                    .parse()?,
+                queue_depth: env::var("ONNX_QUEUE_DEPTH")
+                    .unwrap_or_else(|_| "64".to_string())
+                    .parse()?,
+                executor_threads: env::var("INFERENCE_THREADS")
+                    .map(|v| v.parse())
+                    .unwrap_or_else(|_| {
+                        Ok(std::thread::available_parallelism().map_or(1, |n| n.get()))
+                    })?,
+                batch_window: Duration::from_millis(
+                    env::var("BATCH_WINDOW_MS")
+                        .unwrap_or_else(|_| "10".to_string())
+                        .parse()?,
+                ),
+                max_batch_size: env::var("BATCH_MAX_SIZE")
+                    .unwrap_or_else(|_| "8".to_string())
+                    .parse()?,
+                dynamic_longest_side: env::var("DYNAMIC_INPUT_SIZE")
+                    .unwrap_or_else(|_| "1024".to_string())
+                    .parse()?,
+                dynamic_size_multiple: env::var("DYNAMIC_INPUT_MULTIPLE")
+                    .unwrap_or_else(|_| "32".to_string())
                     .parse()?,
             },
-            cloudinary: CloudinaryConfig {
-                cloud_name: env::var("CLOUDINARY_CLOUD_NAME")?,
-                api_key: env::var("CLOUDINARY_API_KEY")?,
-                api_secret: env::var("CLOUDINARY_API_SECRET")?,
-                upload_preset: env::var("CLOUDINARY_UPLOAD_PRESET")?,
+            processing: ProcessingConfig {
+                letterbox: env::var("LETTERBOX")
+                    .map(|v| v.parse::<bool>().unwrap_or(false))
+                    .unwrap_or(false),
+                crop_threshold: env::var("CROP_THRESHOLD")
+                    .map(|v| v.parse())
+                    .unwrap_or(Ok(DEFAULT_CROP_THRESHOLD))?,
+                crop_min_area: env::var("CROP_MIN_AREA")
+                    .unwrap_or_else(|_| "0".to_string())
+                    .parse()?,
+                max_output_pixels: env::var("MAX_OUTPUT_PIXELS")
+                    .unwrap_or_else(|_| "40000000".to_string())
+                    .parse()?,
             },
-            model: model_config,
             s3: S3Config {
                 access_key: env::var("AWS_ACCESS_KEY_ID").unwrap_or("".to_string()),
                 secret_key: env::var("AWS_SECRET_ACCESS_KEY").unwrap_or("".to_string()),
@@ -126,6 +236,28 @@ impl AppConfig {
                     .unwrap_or(true),
                 region: env::var("MINIO_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
             },
+            local,
+            default_uploader: env::var("DEFAULT_UPLOADER").ok(),
         })
     }
 }
+
+fn is_wildcard_host(host: &str) -> bool {
+    host.trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+        .is_ok_and(|ip| ip.is_unspecified())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_wildcard_hosts() {
+        assert!(is_wildcard_host("0.0.0.0"));
+        assert!(is_wildcard_host("::"));
+        assert!(is_wildcard_host("[::]"));
+        assert!(!is_wildcard_host("127.0.0.1"));
+        assert!(!is_wildcard_host("example.com"));
+    }
+}
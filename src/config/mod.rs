@@ -1,2 +1,6 @@
 mod app;
-pub use app::AppConfig;
+mod model;
+mod profile;
+pub use app::{AppConfig, InferenceConfig, DEFAULT_CROP_THRESHOLD};
+pub use model::{ModelConfig, ModelSource};
+pub use profile::{ChannelOrder, ModelProfile, OutputActivation, TensorLayout};
@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::profile::{ModelProfile, ProfileSpec};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModelSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl FromStr for ModelSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "small" => Ok(ModelSize::Small),
+            "medium" => Ok(ModelSize::Medium),
+            "large" => Ok(ModelSize::Large),
+            _ => Err(anyhow!(
+                "Invalid model size. Valid values are: small, medium, large"
+            )),
+        }
+    }
+}
+
+impl ModelSize {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelSize::Small => "small",
+            ModelSize::Medium => "medium",
+            ModelSize::Large => "large",
+        }
+    }
+
+    pub fn get_model_path(&self) -> String {
+        format!("models/{}.onnx", self.name())
+    }
+}
+
+/// A model loaded at startup and selectable per request by name
+#[derive(Debug, Clone)]
+pub struct ModelSource {
+    pub name: String,
+    pub path: String,
+    pub profile: ModelProfile,
+    /// Sessions kept for this model, `None` to use the shared default
+    pub pool_size: Option<usize>,
+}
+
+impl FromStr for ModelSource {
+    type Err = anyhow::Error;
+
+    /// Parses either a model size (`large`) or a custom model as `name=path/to/model.onnx`,
+    /// optionally followed by its session count (`large:2`, `name=path/to/model.onnx:2`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, pool_size) = split_pool_size(s.trim())?;
+        match s.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => Ok(Self {
+                name: name.trim().to_lowercase(),
+                path: path.trim().to_string(),
+                profile: ModelProfile::default(),
+                pool_size,
+            }),
+            Some(_) => Err(anyhow!("Invalid custom model '{}', expected name=path", s)),
+            None => {
+                let size = ModelSize::from_str(s.trim())?;
+                Ok(Self {
+                    name: size.name().to_string(),
+                    path: size.get_model_path(),
+                    profile: ModelProfile::default(),
+                    pool_size,
+                })
+            }
+        }
+    }
+}
+
+/// Splits a trailing `:count` off a model entry. Anything after the last colon
+/// that isn't a number is left alone so paths containing colons still work.
+fn split_pool_size(entry: &str) -> Result<(&str, Option<usize>)> {
+    match entry.rsplit_once(':') {
+        Some((model, count)) if !count.is_empty() && count.bytes().all(|b| b.is_ascii_digit()) => {
+            match count.parse() {
+                Ok(0) | Err(_) => Err(anyhow!(
+                    "Invalid session count '{}' for model '{}', expected at least 1",
+                    count,
+                    model
+                )),
+                Ok(size) => Ok((model, Some(size))),
+            }
+        }
+        _ => Ok((entry, None)),
+    }
+}
+
+/// Optional `<model>.json` file next to a model in `MODELS_DIR`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ModelManifest {
+    /// Name used to select the model, defaults to the file stem
+    pub name: Option<String>,
+    /// Pre/post-processing profile, defaults to the imgly profile
+    pub profile: Option<ProfileSpec>,
+    /// Sessions kept for this model, defaults to the shared default
+    pub pool_size: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    /// Model used when a request doesn't select one
+    pub default: String,
+    pub sources: Vec<ModelSource>,
+}
+
+impl ModelConfig {
+    pub(super) fn from_env() -> Result<Self> {
+        let size = env::var("ONNX_MODEL_SIZE")
+            .or_else(|_| env::var("MODEL_SIZE"))
+            .unwrap_or_else(|_| "medium".to_string())
+            .trim()
+            .to_lowercase();
+        let models_dir = env::var("MODELS_DIR").ok();
+
+        // Without an explicit list, a models directory replaces the single sized model
+        let explicit_models = env::var("ONNX_MODELS").ok();
+        let models = match (&explicit_models, &models_dir) {
+            (Some(models), _) => models.clone(),
+            (None, Some(_)) => String::new(),
+            (None, None) => size.clone(),
+        };
+
+        let mut sources: Vec<ModelSource> = Vec::new();
+        for entry in models.split(',').filter(|entry| !entry.trim().is_empty()) {
+            add_source(&mut sources, ModelSource::from_str(entry)?)?;
+        }
+
+        // MODEL_PATH is where the model named by ONNX_MODEL_SIZE lives
+        if let Ok(path) = env::var("MODEL_PATH") {
+            match sources.iter_mut().find(|s| s.name == size) {
+                Some(source) => source.path = path,
+                None if explicit_models.is_none() => sources.push(ModelSource {
+                    name: size.clone(),
+                    path,
+                    profile: ModelProfile::default(),
+                    pool_size: None,
+                }),
+                None => {
+                    return Err(anyhow!(
+                        "MODEL_PATH is set but model '{}' is not listed in ONNX_MODELS",
+                        size
+                    ))
+                }
+            }
+        }
+
+        // Explicitly configured models take precedence over files of the same name
+        if let Some(dir) = &models_dir {
+            for source in scan_models_dir(Path::new(dir))? {
+                if !sources.iter().any(|s| s.name == source.name) {
+                    sources.push(source);
+                }
+            }
+        }
+
+        // Profiles given as name=profile pairs, e.g. portrait=modnet,general=isnet
+        if let Ok(profiles) = env::var("ONNX_MODEL_PROFILES") {
+            for entry in profiles.split(',').filter(|entry| !entry.trim().is_empty()) {
+                let (name, profile) = entry.split_once('=').ok_or_else(|| {
+                    anyhow!("Invalid model profile '{}', expected name=profile", entry)
+                })?;
+                let name = name.trim().to_lowercase();
+                let source = sources
+                    .iter_mut()
+                    .find(|s| s.name == name)
+                    .ok_or_else(|| anyhow!("Profile given for unknown model '{}'", name))?;
+                source.profile = ModelProfile::from_str(profile)?;
+            }
+        }
+
+        let default = match env::var("ONNX_DEFAULT_MODEL") {
+            Ok(name) => name.trim().to_lowercase(),
+            Err(_) => sources
+                .iter()
+                .find(|s| s.name == size)
+                .or_else(|| sources.first())
+                .map(|s| s.name.clone())
+                .ok_or_else(|| anyhow!("No models configured in ONNX_MODELS or MODELS_DIR"))?,
+        };
+
+        if !sources.iter().any(|s| s.name == default) {
+            return Err(anyhow!("Default model '{}' is not configured", default));
+        }
+
+        Ok(Self { default, sources })
+    }
+}
+
+fn add_source(sources: &mut Vec<ModelSource>, source: ModelSource) -> Result<()> {
+    if sources.iter().any(|s| s.name == source.name) {
+        return Err(anyhow!(
+            "Model '{}' is configured more than once",
+            source.name
+        ));
+    }
+    sources.push(source);
+    Ok(())
+}
+
+/// Turns every `.onnx` file in `dir` into a model, named by its sidecar manifest or file stem
+fn scan_models_dir(dir: &Path) -> Result<Vec<ModelSource>> {
+    let mut paths = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read MODELS_DIR {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    let mut sources = Vec::new();
+    for path in paths
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|ext| ext == "onnx"))
+    {
+        let manifest = read_manifest(&path.with_extension("json"))?;
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow!("Invalid model file name {}", path.display()))?;
+
+        sources.push(ModelSource {
+            name: manifest
+                .name
+                .unwrap_or_else(|| stem.to_string())
+                .to_lowercase(),
+            path: path.to_string_lossy().into_owned(),
+            profile: manifest
+                .profile
+                .map(ProfileSpec::resolve)
+                .transpose()?
+                .unwrap_or_default(),
+            pool_size: manifest.pool_size,
+        });
+    }
+
+    Ok(sources)
+}
+
+fn read_manifest(path: &Path) -> Result<ModelManifest> {
+    if !path.exists() {
+        return Ok(ModelManifest::default());
+    }
+
+    let contents = fs::read_to_string(path)?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid model manifest {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_session_counts() {
+        let sized = ModelSource::from_str("large:2").unwrap();
+        assert_eq!(
+            (sized.name.as_str(), sized.path.as_str(), sized.pool_size),
+            ("large", "models/large.onnx", Some(2))
+        );
+
+        let custom = ModelSource::from_str("Custom=models/custom.onnx:8").unwrap();
+        assert_eq!(
+            (custom.name.as_str(), custom.path.as_str(), custom.pool_size),
+            ("custom", "models/custom.onnx", Some(8))
+        );
+
+        let plain = ModelSource::from_str("small").unwrap();
+        assert_eq!(plain.pool_size, None);
+    }
+
+    #[test]
+    fn leaves_colons_in_paths_alone() {
+        let source = ModelSource::from_str(r"custom=C:\models\custom.onnx").unwrap();
+        assert_eq!(source.path, r"C:\models\custom.onnx");
+        assert_eq!(source.pool_size, None);
+    }
+
+    #[test]
+    fn rejects_empty_pools() {
+        assert!(ModelSource::from_str("large:0").is_err());
+        assert!(ModelSource::from_str("large:99999999999999999999999").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TensorLayout {
+    Nchw,
+    Nhwc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    Rgb,
+    Bgr,
+}
+
+/// How raw model output is mapped to an alpha value in `[0, 1]`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputActivation {
+    /// Output is already an alpha value
+    None,
+    /// Output is a logit
+    Sigmoid,
+    /// Output is rescaled so its minimum is 0 and maximum is 1
+    MinMax,
+}
+
+/// Describes how images are turned into model input and how the model output
+/// is turned back into an alpha mask. Pixels are normalized as
+/// `(value / 255 - mean) / std` per channel.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ModelProfile {
+    pub layout: TensorLayout,
+    pub channel_order: ChannelOrder,
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+    /// Input (height, width), only needed when the model doesn't declare it
+    pub input_size: Option<(usize, usize)>,
+    pub output_index: usize,
+    pub output_activation: OutputActivation,
+}
+
+impl Default for ModelProfile {
+    /// The imgly background-removal models, normalized with mean 128 and std 256
+    fn default() -> Self {
+        Self {
+            layout: TensorLayout::Nchw,
+            channel_order: ChannelOrder::Rgb,
+            mean: [128. / 255.; 3],
+            std: [256. / 255.; 3],
+            input_size: None,
+            output_index: 0,
+            output_activation: OutputActivation::None,
+        }
+    }
+}
+
+const IMAGENET_MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const IMAGENET_STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+impl FromStr for ModelProfile {
+    type Err = anyhow::Error;
+
+    /// Looks up the profile of a known model family
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let profile = match s.trim().to_lowercase().as_str() {
+            "imgly" => Self::default(),
+            "u2net" => Self {
+                mean: IMAGENET_MEAN,
+                std: IMAGENET_STD,
+                input_size: Some((320, 320)),
+                output_activation: OutputActivation::MinMax,
+                ..Self::default()
+            },
+            "isnet" => Self {
+                mean: [0.5; 3],
+                std: [1.; 3],
+                input_size: Some((1024, 1024)),
+                output_activation: OutputActivation::MinMax,
+                ..Self::default()
+            },
+            "birefnet" => Self {
+                mean: IMAGENET_MEAN,
+                std: IMAGENET_STD,
+                input_size: Some((1024, 1024)),
+                output_activation: OutputActivation::Sigmoid,
+                ..Self::default()
+            },
+            "modnet" => Self {
+                mean: [0.5; 3],
+                std: [0.5; 3],
+                input_size: Some((512, 512)),
+                ..Self::default()
+            },
+            _ => {
+                return Err(anyhow!(
+                    "Invalid model profile '{}'. Valid values are: imgly, u2net, isnet, birefnet, modnet",
+                    s
+                ))
+            }
+        };
+
+        Ok(profile)
+    }
+}
+
+impl FromStr for TensorLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "nchw" => Ok(TensorLayout::Nchw),
+            "nhwc" => Ok(TensorLayout::Nhwc),
+            _ => Err(anyhow!(
+                "Invalid layout '{}'. Valid values are: nchw, nhwc",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for ChannelOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "rgb" => Ok(ChannelOrder::Rgb),
+            "bgr" => Ok(ChannelOrder::Bgr),
+            _ => Err(anyhow!(
+                "Invalid channel order '{}'. Valid values are: rgb, bgr",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for OutputActivation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(OutputActivation::None),
+            "sigmoid" => Ok(OutputActivation::Sigmoid),
+            "minmax" => Ok(OutputActivation::MinMax),
+            _ => Err(anyhow!(
+                "Invalid output activation '{}'. Valid values are: none, sigmoid, minmax",
+                s
+            )),
+        }
+    }
+}
+
+impl ModelProfile {
+    /// Overrides profile fields with custom metadata properties embedded in the
+    /// model file. `lookup` returns the value of a property, if present.
+    ///
+    /// Recognized properties: `layout`, `channel_order`, `mean` and `std`
+    /// (three comma-separated values), `input_size` (`height,width`),
+    /// `output_index` and `output_activation`.
+    pub fn with_metadata<F>(mut self, lookup: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Option<String>>,
+    {
+        let property = |key: &str| -> Result<Option<String>> {
+            Ok(lookup(key)?.filter(|value| !value.trim().is_empty()))
+        };
+        let invalid = |key: &str, value: &str| anyhow!("Invalid model metadata {}={}", key, value);
+
+        if let Some(value) = property("layout")? {
+            self.layout = value.parse()?;
+        }
+        if let Some(value) = property("channel_order")? {
+            self.channel_order = value.parse()?;
+        }
+        if let Some(value) = property("mean")? {
+            self.mean = parse_floats(&value)
+                .and_then(|values| values.try_into().ok())
+                .ok_or_else(|| invalid("mean", &value))?;
+        }
+        if let Some(value) = property("std")? {
+            self.std = parse_floats(&value)
+                .and_then(|values| values.try_into().ok())
+                .ok_or_else(|| invalid("std", &value))?;
+        }
+        if let Some(value) = property("input_size")? {
+            let size = value
+                .split([',', 'x'])
+                .map(|part| part.trim().parse::<usize>().ok())
+                .collect::<Option<Vec<_>>>();
+            self.input_size = match size.as_deref() {
+                Some(&[height, width]) => Some((height, width)),
+                _ => return Err(invalid("input_size", &value)),
+            };
+        }
+        if let Some(value) = property("output_index")? {
+            self.output_index = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid("output_index", &value))?;
+        }
+        if let Some(value) = property("output_activation")? {
+            self.output_activation = value.parse()?;
+        }
+
+        Ok(self)
+    }
+}
+
+fn parse_floats(value: &str) -> Option<Vec<f32>> {
+    value
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect()
+}
+
+/// A profile in a model manifest, either a known family name or a full description
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ProfileSpec {
+    Preset(String),
+    Custom(ModelProfile),
+}
+
+impl ProfileSpec {
+    pub fn resolve(self) -> Result<ModelProfile> {
+        match self {
+            ProfileSpec::Preset(name) => ModelProfile::from_str(&name),
+            ProfileSpec::Custom(profile) => Ok(profile),
+        }
+    }
+}
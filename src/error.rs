@@ -1,4 +1,4 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use serde_json::json;
 use thiserror::Error;
 
@@ -7,17 +7,34 @@ pub enum AppError {
     #[error("Failed to process image: {0}")]
     ImageProcessing(String),
 
-    #[error("Failed to upload to Cloudinary: {0}")]
-    CloudinaryUpload(String),
+    /// Upload failure, with the name of the storage provider
+    #[error("Failed to upload to {0}: {1}")]
+    Upload(&'static str, String),
 
     #[error("Invalid file format")]
     InvalidFileFormat,
 
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
     #[error("Internal server error: {0}")]
     InternalError(String),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
 }
 
 impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::InvalidFileFormat | AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::ImageProcessing(_) | AppError::Upload(..) | AppError::InternalError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
     fn error_response(&self) -> HttpResponse {
         log::error!("Error: {:?}", self);
 
@@ -25,15 +42,21 @@ impl ResponseError for AppError {
             AppError::InvalidFileFormat => HttpResponse::BadRequest().json(json!({
                 "error": "Invalid file format"
             })),
+            AppError::BadRequest(err) => HttpResponse::BadRequest().json(json!({
+                "error": err
+            })),
             AppError::ImageProcessing(err) => HttpResponse::InternalServerError().json(json!({
                 "error": format!("Failed to process image: {}", err)
             })),
-            AppError::CloudinaryUpload(err) => HttpResponse::InternalServerError().json(json!({
-                "error": format!("Failed to upload to Cloudinary: {}", err)
+            AppError::Upload(provider, err) => HttpResponse::InternalServerError().json(json!({
+                "error": format!("Failed to upload to {}: {}", provider, err)
             })),
             AppError::InternalError(err) => HttpResponse::InternalServerError().json(json!({
                 "error": format!("Internal server error: {}", err)
             })),
+            AppError::ServiceUnavailable(err) => HttpResponse::ServiceUnavailable().json(json!({
+                "error": format!("Service unavailable: {}", err)
+            })),
         }
     }
 }
@@ -0,0 +1,10 @@
+//! Background removal service. The binary in `main.rs` runs the HTTP server;
+//! the image pipeline, including `services::image::alpha_matting`, can also
+//! be used directly as a library.
+
+pub mod config;
+pub mod error;
+pub mod routes;
+pub mod server;
+pub mod services;
+pub mod utils;
@@ -1,17 +1,11 @@
 use dotenvy::dotenv;
 use std::sync::Arc;
 
-mod config;
-mod error;
-mod routes;
-mod server;
-mod services;
-mod utils;
-
-use config::AppConfig;
-use server::{create_server, setup::initialize_uploaders, AppState};
-use services::onnx::onnx_session;
-use utils::logging::setup_logging;
+use rmbg::config::AppConfig;
+use rmbg::server::{create_server, initialize_uploaders, AppState};
+use rmbg::services::executor::BlockingExecutor;
+use rmbg::services::model::ModelRegistry;
+use rmbg::utils::logging::setup_logging;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -29,9 +23,31 @@ async fn main() -> std::io::Result<()> {
     log::info!("Creating temporary upload directory: {}", tmp_dir);
     std::fs::create_dir_all(&tmp_dir)?;
 
-    // Initialize ONNX session
-    let session = Arc::new(onnx_session(&config.model.path).expect("Failed to load ONNX model"));
-    log::info!("Loaded ONNX model: {:?}", config.model.size);
+    // Initialize the thread pool that runs CPU-bound image work
+    let executor = Arc::new(
+        BlockingExecutor::new(config.inference.executor_threads)
+            .expect("Failed to start inference thread pool"),
+    );
+    log::info!(
+        "Started inference thread pool with {} threads",
+        executor.num_threads()
+    );
+
+    // Load every configured model with its own session pool and batching scheduler
+    let models = Arc::new(
+        ModelRegistry::load(&config.model, Arc::clone(&executor), &config.inference)
+            .expect("Failed to load ONNX models"),
+    );
+    for model in models.models() {
+        log::info!(
+            "Loaded ONNX model '{}' from {} ({} sessions, max batch size {})",
+            model.name,
+            model.path,
+            model.pool_size(),
+            model.batcher.max_batch_size()
+        );
+    }
+    log::info!("Default ONNX model: {}", models.default_name());
 
     // Initialize uploaders
     let uploaders = initialize_uploaders(&config)
@@ -39,7 +55,7 @@ async fn main() -> std::io::Result<()> {
         .expect("Failed to initialize uploaders");
 
     // Create application state
-    let app_state = AppState::new(Arc::clone(&config), session, uploaders);
+    let app_state = AppState::new(Arc::clone(&config), models, executor, uploaders);
 
     log::info!(
         "Starting server at {}:{}",
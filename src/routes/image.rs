@@ -1,33 +1,170 @@
 use crate::error::AppError;
 use crate::server::AppState;
 use crate::services::{
-    image::{find_alpha_bounds, process_image},
-    upload::{ImageUploader, UploaderType},
+    executor::BlockingExecutor,
+    image::{
+        output_pixels, parse_color, parse_size, process_image, Align, AlphaAdjust, Background,
+        BackgroundBlur, BackgroundFit, BackgroundImage, CanvasLayout, Encoding, Fill, Gradient,
+        MaskCleanup, MaskDepth, OutputFormat, OutputMode, PngCompression, ProcessOptions,
+        ProcessedImage, RefineMethod, MAX_MATTING_BAND,
+    },
+    model::Model,
+    onnx::PoolError,
+    upload::{ImageUploader, UploadedFile, UploaderRegistry, UploaderType},
 };
 use actix_multipart::form::{tempfile::TempFile, MultipartForm};
-use actix_web::{post, web, HttpResponse};
-use futures::future::try_join_all;
-use image::imageops;
+use actix_web::{
+    http::{header, StatusCode},
+    post, web, HttpResponse, ResponseError,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use bytes::Bytes;
+use futures::{future::join_all, stream};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashSet;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::sync::Arc;
+use tokio::sync::mpsc;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// How processed images are returned to the caller
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Delivery {
+    /// Upload to storage and return the URLs
+    #[default]
+    Upload,
+    /// Return a single image as the response body
+    Binary,
+    /// Return the images inline as data URLs
+    Base64,
+    /// Return all images in a ZIP archive
+    Zip,
+}
 
 #[derive(Deserialize)]
 struct ProcessQuery {
     crop: Option<bool>,
+    crop_threshold: Option<u8>,
+    crop_min_area: Option<u32>,
+    padding: Option<String>,
+    aspect: Option<String>,
+    canvas_size: Option<String>,
+    #[serde(default)]
+    align: Align,
+    canvas_color: Option<String>,
+    bg_color: Option<String>,
+    bg_gradient: Option<String>,
+    bg_gradient_angle: Option<f32>,
+    #[serde(default)]
+    bg_fit: BackgroundFit,
+    bg_position: Option<String>,
+    blur: Option<bool>,
+    blur_radius: Option<u32>,
+    blur_falloff: Option<u32>,
     #[serde(default)]
-    upload: UploaderType,
+    output: OutputMode,
+    mask_depth: Option<u8>,
+    #[serde(default)]
+    output_format: OutputFormat,
+    quality: Option<u8>,
+    #[serde(default)]
+    png_compression: PngCompression,
+    lossless: Option<bool>,
+    flatten_color: Option<String>,
+    letterbox: Option<bool>,
+    max_size: Option<u32>,
+    #[serde(default)]
+    refine: RefineMethod,
+    refine_radius: Option<u32>,
+    refine_eps: Option<f32>,
+    matting: Option<bool>,
+    matting_band: Option<u32>,
+    decontaminate: Option<bool>,
+    min_island_area: Option<u32>,
+    fill_holes: Option<bool>,
+    max_hole_area: Option<u32>,
+    keep_largest: Option<u32>,
+    alpha_gamma: Option<f32>,
+    alpha_contrast: Option<f32>,
+    alpha_threshold: Option<u8>,
+    erode: Option<u32>,
+    dilate: Option<u32>,
+    feather: Option<f32>,
+    #[serde(default)]
+    delivery: Delivery,
+    upload: Option<UploaderType>,
+    /// Discard every result when any file fails
+    atomic: Option<bool>,
+    model: Option<String>,
 }
 
 #[derive(Debug, MultipartForm)]
 struct UploadForm {
     #[multipart(rename = "files", limit = "32MiB")]
     files: Vec<TempFile>,
+    /// Image the cut-outs are composited onto
+    #[multipart(rename = "background", limit = "32MiB")]
+    background: Option<TempFile>,
+}
+
+/// Outcome of a single file in a batch
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FileStatus {
+    Success,
+    Failed,
+    /// Succeeded, then discarded because another file failed with atomic=true
+    RolledBack,
 }
 
 #[derive(Debug, Serialize)]
 struct ProcessedImageResult {
-    secure_url: String,
+    index: usize,
+    filename: Option<String>,
+    status: FileStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secure_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl ProcessedImageResult {
+    fn new(index: usize, filename: Option<String>) -> Self {
+        Self {
+            index,
+            filename,
+            status: FileStatus::Success,
+            secure_url: None,
+            data_url: None,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, error: &AppError) {
+        self.status = FileStatus::Failed;
+        self.error = Some(error.to_string());
+    }
+
+    /// Records the outcome of deleting an uploaded `file` while rolling back
+    fn roll_back(&mut self, file: UploadedFile, deleted: anyhow::Result<bool>) {
+        let reason = match deleted {
+            Ok(true) => {
+                self.status = FileStatus::RolledBack;
+                return;
+            }
+            Ok(false) => "the storage provider did not delete the file".to_string(),
+            Err(e) => e.to_string(),
+        };
+        // The file is still stored, so keep its URL for cleanup
+        log::error!("Failed to roll back upload {}: {}", file.id, reason);
+        self.secure_url = Some(file.url);
+        self.error = Some(format!("Rollback failed: {}", reason));
+    }
 }
 
 #[post("/process")]
@@ -36,12 +173,33 @@ pub async fn process_and_upload(
     app_state: web::Data<AppState>,
     query: web::Query<ProcessQuery>,
 ) -> Result<HttpResponse, AppError> {
-    let uploader = app_state
-        .uploaders
-        .get(&query.upload)
-        .ok_or_else(|| AppError::InternalError("Uploader not found".into()))?;
+    // Only uploads need a storage provider
+    let uploader = match query.delivery {
+        Delivery::Upload => Some(
+            app_state
+                .uploaders
+                .get(query.upload)
+                .ok_or_else(|| uploader_unavailable(&app_state.uploaders, query.upload))?,
+        ),
+        _ => None,
+    };
+
+    let model = app_state
+        .models
+        .get(query.model.as_deref())
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "Unknown model '{}'. Available models: {}",
+                query.model.as_deref().unwrap_or_default(),
+                app_state.models.names().join(", ")
+            ))
+        })?;
 
-    log::info!("Using uploader: {:?}", query.upload);
+    log::info!("Using delivery: {:?}", query.delivery);
+    if let Some((uploader_type, _)) = uploader {
+        log::info!("Using uploader: {}", uploader_type.name());
+    }
+    log::info!("Using model: {}", model.name);
     log::info!("Received form data with {} files", form.files.len());
 
     if form.files.is_empty() {
@@ -49,18 +207,178 @@ pub async fn process_and_upload(
         return Err(AppError::InvalidFileFormat);
     }
 
+    if query.delivery == Delivery::Binary && form.files.len() != 1 {
+        return Err(AppError::BadRequest(
+            "delivery=binary returns a single image, use base64 or zip for several files".into(),
+        ));
+    }
+
+    if query.max_size == Some(0) {
+        return Err(AppError::BadRequest(
+            "max_size must be greater than 0".into(),
+        ));
+    }
+
+    if query
+        .refine_eps
+        .is_some_and(|eps| !eps.is_finite() || eps <= 0.)
+    {
+        return Err(AppError::BadRequest(
+            "refine_eps must be greater than 0".into(),
+        ));
+    }
+
+    if query
+        .matting_band
+        .is_some_and(|band| band == 0 || band > MAX_MATTING_BAND)
+    {
+        return Err(AppError::BadRequest(format!(
+            "matting_band must be between 1 and {}",
+            MAX_MATTING_BAND
+        )));
+    }
+
+    if query.keep_largest == Some(0) {
+        return Err(AppError::BadRequest(
+            "keep_largest must be greater than 0".into(),
+        ));
+    }
+
+    for (name, value) in [
+        ("alpha_gamma", query.alpha_gamma),
+        ("alpha_contrast", query.alpha_contrast),
+    ] {
+        if value.is_some_and(|v| !v.is_finite() || v <= 0.) {
+            return Err(AppError::BadRequest(format!(
+                "{} must be greater than 0",
+                name
+            )));
+        }
+    }
+
+    if query
+        .feather
+        .is_some_and(|sigma| !sigma.is_finite() || sigma < 0.)
+    {
+        return Err(AppError::BadRequest("feather must not be negative".into()));
+    }
+
+    let canvas = CanvasLayout {
+        padding: parse_param(query.padding.as_deref(), str::parse)?,
+        aspect: parse_param(query.aspect.as_deref(), str::parse)?,
+        size: parse_param(query.canvas_size.as_deref(), parse_size)?,
+        align: query.align,
+        color: parse_param(query.canvas_color.as_deref(), parse_color)?,
+    };
+
+    let background =
+        parse_background(&query, form.background.as_ref(), &app_state.executor).await?;
+
+    let blur = query.blur.unwrap_or(false).then_some(BackgroundBlur {
+        radius: query.blur_radius,
+        falloff: query.blur_falloff,
+    });
+    if blur.is_some() && background.is_some() {
+        return Err(AppError::BadRequest(
+            "blur can't be combined with a replacement background".into(),
+        ));
+    }
+    if blur.is_some() && query.output != OutputMode::Cutout {
+        return Err(AppError::BadRequest(
+            "blur is only available with output=cutout".into(),
+        ));
+    }
+
+    let mask_depth = match query.mask_depth {
+        None | Some(8) => MaskDepth::Eight,
+        Some(16) => MaskDepth::Sixteen,
+        Some(_) => return Err(AppError::BadRequest("mask_depth must be 8 or 16".into())),
+    };
+
+    if query
+        .quality
+        .is_some_and(|quality| !(1..=100).contains(&quality))
+    {
+        return Err(AppError::BadRequest(
+            "quality must be between 1 and 100".into(),
+        ));
+    }
+
+    // WebP is lossless unless a quality is given
+    let lossless = query.lossless.unwrap_or(query.quality.is_none());
+    if query.output_format == OutputFormat::Webp && lossless && query.quality.is_some() {
+        return Err(AppError::BadRequest(
+            "quality only applies to lossy WebP, drop it or set lossless=false".into(),
+        ));
+    }
+
+    let encoding = Encoding {
+        format: query.output_format,
+        quality: query.quality,
+        lossless,
+        png_compression: query.png_compression,
+        flatten_color: parse_param(query.flatten_color.as_deref(), parse_color)?,
+    };
+
+    let options = ProcessOptions {
+        crop: query.crop.unwrap_or(false),
+        crop_threshold: query
+            .crop_threshold
+            .unwrap_or(app_state.config.processing.crop_threshold),
+        crop_min_area: query
+            .crop_min_area
+            .unwrap_or(app_state.config.processing.crop_min_area),
+        canvas,
+        background,
+        blur,
+        output: query.output,
+        mask_depth,
+        encoding,
+        letterbox: query
+            .letterbox
+            .unwrap_or(app_state.config.processing.letterbox),
+        max_size: query.max_size,
+        refine: query.refine,
+        refine_radius: query.refine_radius,
+        refine_eps: query.refine_eps,
+        matting: query.matting.unwrap_or(false),
+        matting_band: query.matting_band,
+        cleanup: MaskCleanup {
+            min_island_area: query.min_island_area,
+            fill_holes: query.fill_holes.unwrap_or(false),
+            max_hole_area: query.max_hole_area,
+            keep_largest: query.keep_largest,
+        },
+        alpha: AlphaAdjust {
+            gamma: query.alpha_gamma,
+            contrast: query.alpha_contrast,
+            threshold: query.alpha_threshold,
+            erode: query.erode.unwrap_or(0),
+            dilate: query.dilate.unwrap_or(0),
+            feather: query.feather,
+        },
+        // Outputs that keep their alpha need clean edge colours
+        decontaminate: query
+            .decontaminate
+            .unwrap_or(encoding.format.supports_alpha()),
+    };
+
+    check_output_size(&form.files, &options, &app_state).await?;
+
+    let file_names = form
+        .files
+        .iter()
+        .map(|file| file.file_name.clone())
+        .collect::<Vec<_>>();
+
     // Process all files concurrently
     let processing_futures: Vec<_> = form
         .files
         .into_iter()
         .map(|file| {
-            let pr_session = Arc::clone(&app_state.session);
-            let pr_uploader = Arc::clone(uploader);
-            let should_crop = query.crop.unwrap_or(false);
-            let folder = match query.upload {
-                UploaderType::Cloudinary => &app_state.config.cloudinary.upload_preset,
-                _ => "",
-            };
+            let pr_model = Arc::clone(model);
+            let pr_executor = Arc::clone(&app_state.executor);
+            let options = options.clone();
 
             async move {
                 log::info!("Processing file: {:?}", file.file_name);
@@ -77,95 +395,552 @@ pub async fn process_and_upload(
 
                 log::info!("File size: {} bytes", image_data.len());
 
-                process_single_image(image_data, &pr_session, &*pr_uploader, should_crop, &folder)
-                    .await
+                process_single_image(image_data, &pr_model, &pr_executor, options).await
             }
         })
         .collect();
 
-    // Wait for all processing to complete
-    let results = try_join_all(processing_futures).await?;
+    // Wait for all processing to complete, a failed file doesn't stop the others
+    let outcomes = join_all(processing_futures).await;
 
     log::info!(
-        "Successfully processed and uploaded {} images",
-        results.len()
+        "Processed {} of {} images",
+        outcomes.iter().filter(|outcome| outcome.is_ok()).count(),
+        outcomes.len()
     );
 
-    Ok(HttpResponse::Ok().json(json!({
+    // With atomic=true a single failure discards the files that succeeded
+    let atomic = query.atomic.unwrap_or(false);
+
+    match query.delivery {
+        Delivery::Upload => {
+            let (uploader_type, uploader) =
+                uploader.ok_or_else(|| uploader_unavailable(&app_state.uploaders, query.upload))?;
+            let folder = match uploader_type {
+                UploaderType::Cloudinary => &app_state.config.cloudinary.upload_preset,
+                _ => "",
+            };
+            // Nothing is uploaded when an atomic batch has already failed
+            let skip_uploads = atomic && outcomes.iter().any(Result::is_err);
+            let uploads = join_all(outcomes.into_iter().map(|outcome| async move {
+                let image = outcome?;
+                if skip_uploads {
+                    return Ok(None);
+                }
+                upload_to_storage(
+                    &**uploader,
+                    uploader_type,
+                    &image.data,
+                    image.format.extension(),
+                    folder,
+                )
+                .await
+                .map(Some)
+            }))
+            .await;
+
+            let error_status = first_error_status(&uploads);
+            let rollback = atomic && error_status.is_some();
+            let results = join_all(uploads.into_iter().zip(file_names).enumerate().map(
+                |(index, (upload, filename))| async move {
+                    let mut result = ProcessedImageResult::new(index, filename);
+                    match upload {
+                        Ok(None) => result.status = FileStatus::RolledBack,
+                        Ok(Some(file)) if rollback => {
+                            let deleted = uploader.delete(&file.id).await;
+                            result.roll_back(file, deleted)
+                        }
+                        Ok(Some(file)) => result.secure_url = Some(file.url),
+                        Err(e) => result.fail(&e),
+                    }
+                    result
+                },
+            ))
+            .await;
+
+            Ok(batch_response(results, error_status))
+        }
+        Delivery::Base64 => {
+            let error_status = first_error_status(&outcomes);
+            let rollback = atomic && error_status.is_some();
+            let results = outcomes
+                .into_iter()
+                .zip(file_names)
+                .enumerate()
+                .map(|(index, (outcome, filename))| {
+                    let mut result = ProcessedImageResult::new(index, filename);
+                    match outcome {
+                        Ok(_) if rollback => result.status = FileStatus::RolledBack,
+                        Ok(image) => {
+                            result.data_url = Some(format!(
+                                "data:{};base64,{}",
+                                image.format.content_type(),
+                                BASE64.encode(&image.data)
+                            ))
+                        }
+                        Err(e) => result.fail(&e),
+                    }
+                    result
+                })
+                .collect();
+
+            Ok(batch_response(results, error_status))
+        }
+        Delivery::Zip => {
+            // A single archive can't report per-file errors, so any failure fails the request
+            let images = outcomes.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+            // The archive is written on a blocking thread straight into the
+            // response body, which waits on the client as it reads
+            let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+            tokio::task::spawn_blocking(move || {
+                let writer = BufWriter::with_capacity(ZIP_CHUNK_SIZE, ChannelWriter(tx.clone()));
+                if let Err(e) = write_archive(images, &file_names, writer) {
+                    log::error!("Failed to stream ZIP archive: {}", e);
+                    let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+                }
+            });
+            let body = stream::unfold(rx, |mut rx| async move {
+                rx.recv().await.map(|chunk| (chunk, rx))
+            });
+
+            Ok(HttpResponse::Ok()
+                .content_type("application/zip")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"images.zip\"",
+                ))
+                .streaming(body))
+        }
+        Delivery::Binary => {
+            let image = outcomes
+                .into_iter()
+                .next()
+                .ok_or(AppError::InvalidFileFormat)??;
+
+            Ok(HttpResponse::Ok()
+                .content_type(image.format.content_type())
+                .body(image.data))
+        }
+    }
+}
+
+/// Status code of the first failed file, if any
+fn first_error_status<T>(outcomes: &[Result<T, AppError>]) -> Option<StatusCode> {
+    outcomes
+        .iter()
+        .find_map(|outcome| outcome.as_ref().err())
+        .map(ResponseError::status_code)
+}
+
+/// Responds with the per-file results and a summary. Mixed outcomes are a
+/// 207 Multi-Status, a batch where nothing succeeded takes the status of its
+/// first error.
+fn batch_response(
+    results: Vec<ProcessedImageResult>,
+    error_status: Option<StatusCode>,
+) -> HttpResponse {
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let succeeded = count(FileStatus::Success);
+    let failed = count(FileStatus::Failed);
+    let rolled_back = count(FileStatus::RolledBack);
+
+    log::info!(
+        "Batch finished: {} succeeded, {} failed, {} rolled back",
+        succeeded,
+        failed,
+        rolled_back
+    );
+
+    let status = match error_status {
+        None => StatusCode::OK,
+        Some(_) if succeeded > 0 => StatusCode::MULTI_STATUS,
+        Some(status) => status,
+    };
+
+    HttpResponse::build(status).json(json!({
+        "summary": {
+            "total": results.len(),
+            "succeeded": succeeded,
+            "failed": failed,
+            "rolled_back": rolled_back,
+        },
         "results": results
-    })))
+    }))
+}
+
+/// Builds the replacement background from the `bg_*` parameters and the
+/// optional `background` image field
+async fn parse_background(
+    query: &ProcessQuery,
+    file: Option<&TempFile>,
+    executor: &BlockingExecutor,
+) -> Result<Option<Background>, AppError> {
+    let fill = match (&query.bg_color, &query.bg_gradient) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest(
+                "bg_color and bg_gradient can't be combined".into(),
+            ))
+        }
+        (Some(color), None) => Some(Fill::Color(
+            parse_color(color).map_err(|e| AppError::BadRequest(e.to_string()))?,
+        )),
+        (None, Some(colors)) => Some(Fill::Gradient(
+            Gradient::parse(colors, query.bg_gradient_angle)
+                .map_err(|e| AppError::BadRequest(e.to_string()))?,
+        )),
+        (None, None) => None,
+    };
+
+    let image = match file {
+        Some(file) => {
+            let data = tokio::fs::read(file.file.path())
+                .await
+                .map_err(|e| AppError::InternalError(e.to_string()))?;
+            let image = executor
+                .run(move || image::load_from_memory(&data).map(|image| image.into_rgba8()))
+                .await
+                .map_err(|e| AppError::InternalError(e.to_string()))?
+                .map_err(|e| AppError::BadRequest(format!("Invalid background image: {}", e)))?;
+
+            Some(BackgroundImage {
+                image: Arc::new(image),
+                fit: query.bg_fit,
+                position: parse_param(query.bg_position.as_deref(), str::parse)?
+                    .unwrap_or_default(),
+            })
+        }
+        None => None,
+    };
+
+    if fill.is_none() && image.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Background { fill, image }))
+}
+
+/// Parses an optional query parameter, rejecting invalid values with a 400
+fn parse_param<T>(
+    value: Option<&str>,
+    parse: impl Fn(&str) -> anyhow::Result<T>,
+) -> Result<Option<T>, AppError> {
+    value
+        .map(parse)
+        .transpose()
+        .map_err(|e| AppError::BadRequest(e.to_string()))
 }
 
 async fn process_single_image(
     image_data: Vec<u8>,
-    session: &Arc<ort::Session>,
-    uploader: &dyn ImageUploader,
-    should_crop: bool,
-    folder: &str,
-) -> Result<ProcessedImageResult, AppError> {
+    model: &Model,
+    executor: &BlockingExecutor,
+    options: ProcessOptions,
+) -> Result<ProcessedImage, AppError> {
     // Process image with ONNX model
     log::info!("Processing image with ONNX model");
-    let processed = process_image(session, &image_data).await.map_err(|e| {
-        log::error!("Image processing failed: {}", e);
-        AppError::ImageProcessing(e.to_string())
-    })?;
-
-    // If no cropping requested, upload the processed image directly
-    if !should_crop {
-        let secure_url = upload_to_storage(uploader, &processed.data, &folder).await?;
-        return Ok(ProcessedImageResult { secure_url });
-    }
-
-    // Handle cropping
-    let img = image::load_from_memory(&processed.data)
-        .map_err(|e| AppError::ImageProcessing(e.to_string()))?;
-    let mut output_img = img.to_rgba8();
-
-    // Find alpha bounds for cropping
-    let bounds = find_alpha_bounds(&output_img);
-
-    match bounds {
-        Some((min_x, min_y, max_x, max_y)) => {
-            // Crop the image
-            let cropped_img = imageops::crop(
-                &mut output_img,
-                min_x,
-                min_y,
-                max_x - min_x + 1,
-                max_y - min_y + 1,
-            )
-            .to_image();
-
-            // Convert cropped image to PNG format
-            let mut buffer = std::io::Cursor::new(Vec::new());
-            cropped_img
-                .write_to(&mut buffer, image::ImageFormat::Png)
-                .map_err(|e| AppError::ImageProcessing(e.to_string()))?;
-
-            // Upload the final cropped image
-            let secure_url = upload_to_storage(uploader, &buffer.into_inner(), &folder).await?;
-            Ok(ProcessedImageResult { secure_url })
+    process_image(model, executor, image_data, options)
+        .await
+        .map_err(|e| {
+            log::error!("Image processing failed: {}", e);
+            processing_error(e)
+        })
+}
+
+/// Maps a processing failure to the status it's reported with. Files that
+/// can't be decoded are the client's fault.
+fn processing_error(e: anyhow::Error) -> AppError {
+    if e.downcast_ref::<PoolError>().is_some() {
+        return AppError::ServiceUnavailable(e.to_string());
+    }
+    match e.downcast_ref::<::image::ImageError>() {
+        Some(::image::ImageError::Decoding(_) | ::image::ImageError::Unsupported(_)) => {
+            AppError::InvalidFileFormat
+        }
+        _ => AppError::ImageProcessing(e.to_string()),
+    }
+}
+
+/// Size of the chunks the ZIP archive is streamed in
+const ZIP_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Sends everything written to it as chunks of a streamed response body
+struct ChannelWriter(mpsc::Sender<io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the images into an uncompressed ZIP archive, named after the
+/// uploaded files, releasing each image once it's written. Image formats
+/// are already compressed.
+fn write_archive<W: Write>(
+    images: Vec<ProcessedImage>,
+    file_names: &[Option<String>],
+    writer: W,
+) -> anyhow::Result<()> {
+    let mut archive = ZipWriter::new_stream(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut used_names = HashSet::new();
+
+    for (index, image) in images.into_iter().enumerate() {
+        let stem = file_names
+            .get(index)
+            .and_then(|name| name.as_deref())
+            .and_then(|name| Path::new(name).file_stem())
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| !stem.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("image-{}", index + 1));
+
+        let mut name = format!("{}.{}", stem, image.format.extension());
+        if !used_names.insert(name.clone()) {
+            name = format!("{}-{}.{}", stem, index + 1, image.format.extension());
+            used_names.insert(name.clone());
         }
-        None => {
-            // If no valid bounds found, upload the processed image without cropping
-            let secure_url = upload_to_storage(uploader, &processed.data, &folder).await?;
-            Ok(ProcessedImageResult { secure_url })
+
+        archive.start_file(name, options)?;
+        archive.write_all(&image.data)?;
+    }
+
+    archive.finish()?.into_inner().flush()?;
+    Ok(())
+}
+
+/// Rejects requests whose canvas would exceed `MAX_OUTPUT_PIXELS` for any of
+/// the files, reading only the image headers
+async fn check_output_size(
+    files: &[TempFile],
+    options: &ProcessOptions,
+    app_state: &AppState,
+) -> Result<(), AppError> {
+    let max_pixels = app_state.config.processing.max_output_pixels;
+    let paths = files
+        .iter()
+        .map(|file| file.file.path().to_path_buf())
+        .collect::<Vec<_>>();
+
+    // Files whose header can't be read fail later, during decoding
+    let dimensions = web::block(move || {
+        paths
+            .iter()
+            .map(|path| {
+                ::image::ImageReader::open(path)
+                    .and_then(|reader| reader.with_guessed_format())
+                    .ok()
+                    .and_then(|reader| reader.into_dimensions().ok())
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| AppError::InternalError(e.to_string()))?;
+
+    for (file, dimensions) in files.iter().zip(dimensions) {
+        let Some(dimensions) = dimensions else {
+            continue;
+        };
+        let pixels = output_pixels(dimensions, options);
+        if pixels > max_pixels {
+            return Err(AppError::BadRequest(format!(
+                "Output for {} would be {} pixels, more than the limit of {}",
+                file.file_name.as_deref().unwrap_or("file"),
+                pixels,
+                max_pixels
+            )));
         }
     }
+
+    Ok(())
+}
+
+/// Error for an upload to a provider that isn't enabled, naming the ones that are
+fn uploader_unavailable(uploaders: &UploaderRegistry, requested: Option<UploaderType>) -> AppError {
+    let enabled = uploaders.names();
+    match requested.or(uploaders.default_type()) {
+        _ if enabled.is_empty() => AppError::BadRequest(
+            "No uploaders are configured, use delivery=binary, base64 or zip".into(),
+        ),
+        Some(uploader_type) => AppError::BadRequest(format!(
+            "Uploader '{}' is not enabled. Enabled uploaders: {}",
+            uploader_type.name(),
+            enabled.join(", ")
+        )),
+        None => AppError::BadRequest(format!(
+            "No uploader selected. Enabled uploaders: {}",
+            enabled.join(", ")
+        )),
+    }
 }
 
 async fn upload_to_storage(
     uploader: &dyn ImageUploader,
+    uploader_type: UploaderType,
     image_data: &[u8],
+    extension: &str,
     folder: &str,
-) -> Result<String, AppError> {
+) -> Result<UploadedFile, AppError> {
     log::info!("Uploading to storage service");
 
     uploader
-        .upload(image_data, "png", folder)
+        .upload(image_data, extension, folder)
         .await
         .map_err(|e| {
-            log::error!("Upload failed: {}", e);
-            AppError::CloudinaryUpload(e.to_string())
+            log::error!("Upload to {} failed: {}", uploader_type.name(), e);
+            AppError::Upload(uploader_type.name(), e.to_string())
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn result(index: usize, status: FileStatus) -> ProcessedImageResult {
+        let mut result = ProcessedImageResult::new(index, None);
+        result.status = status;
+        result
+    }
+
+    async fn read_summary(response: HttpResponse) -> (StatusCode, serde_json::Value) {
+        let status = response.status();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (status, body["summary"].clone())
+    }
+
+    #[test]
+    fn first_error_status_skips_successes() {
+        let outcomes: Vec<Result<(), AppError>> = vec![
+            Ok(()),
+            Err(AppError::InvalidFileFormat),
+            Err(AppError::ServiceUnavailable("busy".into())),
+        ];
+        assert_eq!(first_error_status(&outcomes), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(first_error_status::<()>(&[Ok(()), Ok(())]), None);
+    }
+
+    #[actix_web::test]
+    async fn batch_status_follows_outcomes() {
+        let all_succeeded = vec![
+            result(0, FileStatus::Success),
+            result(1, FileStatus::Success),
+        ];
+        let (status, summary) = read_summary(batch_response(all_succeeded, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(summary["succeeded"], 2);
+
+        let mixed = vec![
+            result(0, FileStatus::Success),
+            result(1, FileStatus::Failed),
+        ];
+        let (status, summary) =
+            read_summary(batch_response(mixed, Some(StatusCode::BAD_REQUEST))).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(
+            summary,
+            json!({"total": 2, "succeeded": 1, "failed": 1, "rolled_back": 0})
+        );
+
+        let all_failed = vec![result(0, FileStatus::Failed), result(1, FileStatus::Failed)];
+        let (status, summary) = read_summary(batch_response(
+            all_failed,
+            Some(StatusCode::SERVICE_UNAVAILABLE),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(summary["failed"], 2);
+    }
+
+    #[actix_web::test]
+    async fn atomic_batch_reports_rollbacks() {
+        let file = |id: &str| UploadedFile {
+            url: format!("https://example.com/{}", id),
+            id: id.to_string(),
+        };
+
+        let mut deleted = result(0, FileStatus::Success);
+        deleted.roll_back(file("a"), Ok(true));
+        let mut kept = result(1, FileStatus::Success);
+        kept.roll_back(file("b"), Ok(false));
+        let mut errored = result(2, FileStatus::Success);
+        errored.roll_back(file("c"), Err(anyhow::anyhow!("timed out")));
+        // Uploads skipped because the batch had already failed
+        let skipped = result(3, FileStatus::RolledBack);
+        let failed = result(4, FileStatus::Failed);
+
+        assert_eq!(deleted.status, FileStatus::RolledBack);
+        assert_eq!(deleted.secure_url, None);
+        assert_eq!(kept.secure_url.as_deref(), Some("https://example.com/b"));
+        assert!(kept
+            .error
+            .as_deref()
+            .is_some_and(|e| e.starts_with("Rollback failed")));
+        assert_eq!(errored.error.as_deref(), Some("Rollback failed: timed out"));
+
+        let results = vec![deleted, kept, errored, skipped, failed];
+        let (status, summary) =
+            read_summary(batch_response(results, Some(StatusCode::BAD_REQUEST))).await;
+        // Files whose rollback failed are still stored, so the batch is partial
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        assert_eq!(
+            summary,
+            json!({"total": 5, "succeeded": 2, "failed": 1, "rolled_back": 2})
+        );
+    }
+
+    #[test]
+    fn corrupt_files_are_bad_requests() {
+        let corrupt = ::image::load_from_memory(b"\x89PNG\r\n\x1a\nnot really").unwrap_err();
+        let error = processing_error(corrupt.into());
+        assert!(matches!(error, AppError::InvalidFileFormat));
+        assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+
+        let error = processing_error(anyhow::anyhow!("Model output has no alpha"));
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn archive_names_follow_uploads() {
+        let image = |data: &[u8]| ProcessedImage {
+            data: data.to_vec(),
+            format: OutputFormat::Png,
+        };
+        let images = vec![image(b"first"), image(b"second"), image(b"third")];
+        let file_names = [
+            Some("photo.jpg".to_string()),
+            Some("photo.png".to_string()),
+            None,
+        ];
+
+        let mut buffer = Vec::new();
+        write_archive(images, &file_names, &mut buffer).unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(buffer)).unwrap();
+        let mut entries = Vec::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            let mut data = String::new();
+            file.read_to_string(&mut data).unwrap();
+            entries.push((file.name().to_string(), data));
+        }
+
+        assert_eq!(
+            entries,
+            [
+                ("photo.png".to_string(), "first".to_string()),
+                ("photo-2.png".to_string(), "second".to_string()),
+                ("image-3.png".to_string(), "third".to_string()),
+            ]
+        );
+    }
+}
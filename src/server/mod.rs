@@ -2,5 +2,5 @@
 pub(crate) mod setup;
 mod state;
 
-pub use setup::create_server;
+pub use setup::{create_server, initialize_uploaders};
 pub use state::AppState;
@@ -1,6 +1,10 @@
 // server/setup.rs
 use std::collections::HashMap;
+use std::str::FromStr;
 
+use anyhow::{bail, Context};
+
+use actix_files::Files;
 use actix_multipart::form::tempfile::TempFileConfig;
 use actix_web::http::header;
 use actix_web::middleware::Logger;
@@ -9,28 +13,55 @@ use actix_web::{middleware, web, App, HttpServer};
 use super::state::AppState;
 use crate::config::AppConfig;
 use crate::routes;
-use crate::services::upload::{DynImageUploader, UploaderFactory, UploaderType};
+use crate::services::upload::{UploaderFactory, UploaderRegistry, UploaderType};
 
 // server/setup.rs
 
-pub async fn initialize_uploaders(
-    config: &AppConfig,
-) -> anyhow::Result<HashMap<UploaderType, DynImageUploader>> {
+/// Creates every configured uploader and reports which ones are enabled
+pub async fn initialize_uploaders(config: &AppConfig) -> anyhow::Result<UploaderRegistry> {
     let mut uploaders = HashMap::new();
 
-    // Initialize each uploader type
-    let uploader_types = vec![
-        UploaderType::Cloudinary,
-        UploaderType::S3,
-        UploaderType::Minio,
-    ];
+    for uploader_type in UploaderType::ALL {
+        match UploaderFactory::create_uploader(uploader_type, config)
+            .await
+            .with_context(|| format!("Failed to initialize uploader '{}'", uploader_type.name()))?
+        {
+            Some(uploader) => {
+                log::info!("Uploader '{}' enabled", uploader_type.name());
+                uploaders.insert(uploader_type, uploader);
+            }
+            None => log::info!(
+                "Uploader '{}' disabled, set {} to enable it",
+                uploader_type.name(),
+                uploader_type.required_env()
+            ),
+        }
+    }
+
+    let default = match &config.default_uploader {
+        Some(name) => {
+            let uploader_type = UploaderType::from_str(name)?;
+            if !uploaders.contains_key(&uploader_type) {
+                bail!(
+                    "Default uploader '{}' is not configured",
+                    uploader_type.name()
+                );
+            }
+            Some(uploader_type)
+        }
+        None => UploaderType::ALL
+            .into_iter()
+            .find(|uploader_type| uploaders.contains_key(uploader_type)),
+    };
 
-    for uploader_type in uploader_types {
-        let uploader = UploaderFactory::create_uploader(uploader_type.clone(), config).await?;
-        uploaders.insert(uploader_type, uploader);
+    match default {
+        Some(uploader_type) => log::info!("Default uploader: {}", uploader_type.name()),
+        None => log::warn!(
+            "No uploaders configured, only binary, base64 and zip delivery are available"
+        ),
     }
 
-    Ok(uploaders)
+    Ok(UploaderRegistry::new(uploaders, default))
 }
 
 fn configure_cors() -> middleware::DefaultHeaders {
@@ -53,6 +84,23 @@ pub fn create_server(
     bind_host: String,
     bind_port: u16,
 ) -> std::io::Result<actix_web::dev::Server> {
+    // Stored files are only served when local storage is enabled
+    let stored_files = app_state
+        .config
+        .local
+        .directory
+        .clone()
+        .filter(|_| app_state.uploaders.is_enabled(UploaderType::Local))
+        .map(|directory| {
+            let route = app_state
+                .config
+                .local
+                .route
+                .trim_end_matches('/')
+                .to_owned();
+            (route, directory)
+        });
+
     let server = HttpServer::new(move || {
         App::new()
             .wrap(configure_cors())
@@ -61,6 +109,11 @@ pub fn create_server(
             .app_data(web::FormConfig::default().limit(32 * 1024 * 1024))
             .app_data(configure_temp_files(&tmp_dir))
             .service(web::scope("/api").service(routes::image::process_and_upload))
+            .configure(|cfg| {
+                if let Some((route, directory)) = &stored_files {
+                    cfg.service(Files::new(route, directory));
+                }
+            })
             .service(routes::health::index)
             .wrap(Logger::new("%a %{User-Agent}i"))
             .wrap(Logger::default())
@@ -1,25 +1,29 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::config::AppConfig;
-use crate::services::upload::{DynImageUploader, UploaderType};
+use crate::services::executor::BlockingExecutor;
+use crate::services::model::ModelRegistry;
+use crate::services::upload::UploaderRegistry;
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<AppConfig>,
-    pub session: Arc<ort::Session>,
-    pub uploaders: Arc<HashMap<UploaderType, DynImageUploader>>,
+    pub models: Arc<ModelRegistry>,
+    pub executor: Arc<BlockingExecutor>,
+    pub uploaders: Arc<UploaderRegistry>,
 }
 
 impl AppState {
     pub fn new(
         config: Arc<AppConfig>,
-        session: Arc<ort::Session>,
-        uploaders: HashMap<UploaderType, DynImageUploader>,
+        models: Arc<ModelRegistry>,
+        executor: Arc<BlockingExecutor>,
+        uploaders: UploaderRegistry,
     ) -> Self {
         Self {
             config,
-            session,
+            models,
+            executor,
             uploaders: Arc::new(uploaders),
         }
     }
@@ -0,0 +1,43 @@
+use anyhow::{anyhow, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::panic::{self, AssertUnwindSafe};
+use tokio::sync::oneshot;
+
+/// Dedicated thread pool for CPU-bound work (decoding, tensor building,
+/// inference, encoding) so it never runs on an actix worker thread.
+pub struct BlockingExecutor {
+    pool: ThreadPool,
+}
+
+impl BlockingExecutor {
+    pub fn new(num_threads: usize) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("rmbg-inference-{}", index))
+            .build()?;
+
+        Ok(Self { pool })
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Runs `job` on the executor and waits for its result without blocking the caller's thread
+    pub async fn run<F, R>(&self, job: F) -> Result<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        self.pool.spawn(move || {
+            // A panicking job must not take the executor thread down with it
+            let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(job)));
+        });
+
+        rx.await
+            .map_err(|_| anyhow!("Blocking task was dropped before completing"))?
+            .map_err(|_| anyhow!("Blocking task panicked"))
+    }
+}
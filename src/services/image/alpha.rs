@@ -0,0 +1,249 @@
+use image::{GrayImage, Luma};
+use std::collections::VecDeque;
+
+use super::refine::box_filter;
+
+/// Adjustments of the final alpha values, applied in field order
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlphaAdjust {
+    /// Gamma curve on alpha, values above 1 thin out soft edges
+    pub gamma: Option<f32>,
+    /// Contrast around half opacity, values above 1 harden edges
+    pub contrast: Option<f32>,
+    /// Binarize alpha, pixels at or above the threshold become opaque
+    pub threshold: Option<u8>,
+    /// Shrink the subject by this many pixels
+    pub erode: u32,
+    /// Grow the subject by this many pixels
+    pub dilate: u32,
+    /// Standard deviation in pixels of an approximate Gaussian blur softening the edges
+    pub feather: Option<f32>,
+}
+
+impl AlphaAdjust {
+    pub fn is_enabled(&self) -> bool {
+        self.gamma.is_some()
+            || self.contrast.is_some()
+            || self.threshold.is_some()
+            || self.erode > 0
+            || self.dilate > 0
+            || self.feather.is_some_and(|sigma| sigma > 0.)
+    }
+}
+
+pub fn adjust_alpha(mask: GrayImage, adjust: &AlphaAdjust) -> GrayImage {
+    let mut mask = mask;
+
+    if adjust.gamma.is_some() || adjust.contrast.is_some() {
+        let gamma = adjust.gamma.unwrap_or(1.);
+        let contrast = adjust.contrast.unwrap_or(1.);
+        let curve: [u8; 256] = std::array::from_fn(|a| {
+            let value = (a as f32 / 255.).powf(gamma);
+            let value = (value - 0.5) * contrast + 0.5;
+            (value.clamp(0., 1.) * 255.).round() as u8
+        });
+        mask.pixels_mut().for_each(|p| p[0] = curve[p[0] as usize]);
+    }
+
+    if let Some(threshold) = adjust.threshold {
+        mask.pixels_mut()
+            .for_each(|p| p[0] = if p[0] >= threshold { 255 } else { 0 });
+    }
+
+    if adjust.erode > 0 {
+        mask = morphology(&mask, adjust.erode, Extremum::Min);
+    }
+    if adjust.dilate > 0 {
+        mask = morphology(&mask, adjust.dilate, Extremum::Max);
+    }
+
+    match adjust.feather {
+        Some(sigma) if sigma > 0. => feather(&mask, sigma),
+        _ => mask,
+    }
+}
+
+/// Gaussian blur approximated by three box filter passes, which costs the
+/// same for any `sigma`. Three passes of radius `r` have a variance of `r(r + 1)`.
+fn feather(mask: &GrayImage, sigma: f32) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let radius = ((sigma * sigma + 0.25).sqrt() - 0.5).round().max(1.) as usize;
+
+    let values = mask.pixels().map(|p| p[0] as f32).collect::<Vec<_>>();
+    let once = box_filter(&values, w, h, radius);
+    let twice = box_filter(&once, w, h, radius);
+    let blurred = box_filter(&twice, w, h, radius);
+
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([blurred[y as usize * w + x as usize].round().clamp(0., 255.) as u8])
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Extremum {
+    Min,
+    Max,
+}
+
+/// Grayscale erosion (`Min`) or dilation (`Max`) over a `(2 * radius + 1)`
+/// square window, done separably along rows and then columns
+fn morphology(mask: &GrayImage, radius: u32, extremum: Extremum) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let radius = radius as usize;
+
+    let mut rows = vec![0; w * h];
+    for y in 0..h {
+        let line = &mask.as_raw()[y * w..(y + 1) * w];
+        rows[y * w..(y + 1) * w].copy_from_slice(&sliding_extremum(line, radius, extremum));
+    }
+
+    let mut output = vec![0; w * h];
+    for x in 0..w {
+        let column = (0..h).map(|y| rows[y * w + x]).collect::<Vec<_>>();
+        for (y, value) in sliding_extremum(&column, radius, extremum)
+            .into_iter()
+            .enumerate()
+        {
+            output[y * w + x] = value;
+        }
+    }
+
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([output[y as usize * w + x as usize]])
+    })
+}
+
+/// Minimum or maximum over a window clipped at the ends, using a monotonic deque
+fn sliding_extremum(values: &[u8], radius: usize, extremum: Extremum) -> Vec<u8> {
+    let dominates = |a: u8, b: u8| match extremum {
+        Extremum::Min => a <= b,
+        Extremum::Max => a >= b,
+    };
+
+    let mut output = Vec::with_capacity(values.len());
+    let mut window: VecDeque<usize> = VecDeque::new();
+    let mut next = 0;
+
+    for i in 0..values.len() {
+        // Extend the window to i + radius
+        while next < values.len() && next <= i + radius {
+            while window
+                .back()
+                .is_some_and(|&j| dominates(values[next], values[j]))
+            {
+                window.pop_back();
+            }
+            window.push_back(next);
+            next += 1;
+        }
+        // Drop indices left of i - radius
+        while window.front().is_some_and(|&j| j + radius < i) {
+            window.pop_front();
+        }
+        output.push(values[window[0]]);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[u8]) -> GrayImage {
+        GrayImage::from_raw(values.len() as u32, 1, values.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn sliding_extremum_clips_windows_at_the_ends() {
+        let values = [5, 1, 9, 3, 7];
+        assert_eq!(sliding_extremum(&values, 1, Extremum::Min), [1, 1, 1, 3, 3]);
+        assert_eq!(sliding_extremum(&values, 1, Extremum::Max), [5, 9, 9, 9, 7]);
+        // Equal values must not be dropped from the deque too early
+        assert_eq!(
+            sliding_extremum(&[4, 4, 2, 4, 4], 1, Extremum::Min),
+            [4, 2, 2, 2, 4]
+        );
+    }
+
+    #[test]
+    fn radius_beyond_the_image_covers_everything() {
+        let values = [5, 1, 9, 3, 7];
+        assert_eq!(sliding_extremum(&values, 5, Extremum::Min), [1; 5]);
+        assert_eq!(sliding_extremum(&values, 100, Extremum::Max), [9; 5]);
+
+        let mask = GrayImage::from_fn(3, 2, |x, y| Luma([(x + y * 3) as u8 * 10]));
+        let eroded = morphology(&mask, 3, Extremum::Min);
+        assert!(eroded.pixels().all(|p| p[0] == 0));
+        let dilated = morphology(&mask, 3, Extremum::Max);
+        assert!(dilated.pixels().all(|p| p[0] == 50));
+    }
+
+    #[test]
+    fn erode_and_dilate_at_the_borders() {
+        // A subject touching the left border, with empty space on the right
+        let mask = GrayImage::from_fn(5, 3, |x, _| Luma([if x < 3 { 255 } else { 0 }]));
+
+        let eroded = adjust_alpha(
+            mask.clone(),
+            &AlphaAdjust {
+                erode: 1,
+                ..Default::default()
+            },
+        );
+        // The image border doesn't erode the subject, only the background does
+        assert!(eroded
+            .rows()
+            .all(|r| r.map(|p| p[0]).collect::<Vec<_>>() == [255, 255, 0, 0, 0]));
+
+        let dilated = adjust_alpha(
+            mask,
+            &AlphaAdjust {
+                dilate: 1,
+                ..Default::default()
+            },
+        );
+        assert!(dilated
+            .rows()
+            .all(|r| r.map(|p| p[0]).collect::<Vec<_>>() == [255, 255, 255, 255, 0]));
+    }
+
+    #[test]
+    fn gamma_and_contrast_curve() {
+        let adjust = |gamma, contrast| {
+            adjust_alpha(
+                row(&[0, 64, 128, 191, 255]),
+                &AlphaAdjust {
+                    gamma,
+                    contrast,
+                    ..Default::default()
+                },
+            )
+            .into_raw()
+        };
+
+        // The ends stay fixed, gamma above 1 thins out the middle
+        assert_eq!(adjust(Some(1.), None), [0, 64, 128, 191, 255]);
+        let thinned = adjust(Some(2.), None);
+        assert_eq!((thinned[0], thinned[4]), (0, 255));
+        assert!(thinned[1] < 64 && thinned[2] < 128 && thinned[3] < 191);
+
+        // Contrast pushes values away from half opacity
+        assert_eq!(adjust(None, Some(2.)), [0, 1, 129, 255, 255]);
+        assert_eq!(adjust(None, Some(0.5)), [64, 96, 128, 159, 191]);
+    }
+
+    #[test]
+    fn feather_softens_a_hard_edge() {
+        let mask = GrayImage::from_fn(40, 1, |x, _| Luma([if x < 20 { 255 } else { 0 }]));
+        let feathered = feather(&mask, 3.).into_raw();
+
+        assert_eq!((feathered[0], feathered[39]), (255, 0));
+        assert!(feathered.windows(2).all(|pair| pair[0] >= pair[1]));
+        // The edge stays in place and spreads over a few pixels on each side
+        assert!(feathered[19] > 127 && feathered[20] < 128);
+        assert!(feathered[17] < 255 && feathered[22] > 0);
+    }
+}
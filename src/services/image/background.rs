@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Result};
+use image::{imageops, GenericImageView, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::color::parse_color;
+
+/// Fill behind the background image where it doesn't cover the output
+const DEFAULT_FILL: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// What the cut-out is composited onto
+#[derive(Debug, Clone)]
+pub struct Background {
+    /// Solid colour or gradient at the bottom
+    pub fill: Option<Fill>,
+    /// Image drawn over the fill
+    pub image: Option<BackgroundImage>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Fill {
+    Color(Rgba<u8>),
+    Gradient(Gradient),
+}
+
+/// Linear gradient with evenly spaced colour stops
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    pub colors: Vec<Rgba<u8>>,
+    /// Direction in degrees, as in CSS: 0 points up and 180 (the default) down
+    pub angle: f32,
+}
+
+impl Gradient {
+    /// Parses colours separated by `:`, e.g. `ffffff:d0d0d0`
+    pub fn parse(colors: &str, angle: Option<f32>) -> Result<Self> {
+        let colors = colors
+            .split(':')
+            .map(parse_color)
+            .collect::<Result<Vec<_>>>()?;
+        if colors.len() < 2 {
+            return Err(anyhow!("A gradient needs at least two colours"));
+        }
+        let angle = angle.unwrap_or(180.);
+        if !angle.is_finite() {
+            return Err(anyhow!("Invalid gradient angle"));
+        }
+        Ok(Self { colors, angle })
+    }
+
+    fn render(&self, width: u32, height: u32) -> RgbaImage {
+        let radians = self.angle.to_radians();
+        let (dx, dy) = (radians.sin(), -radians.cos());
+        let length = (width as f32 * dx.abs() + height as f32 * dy.abs()).max(1.);
+        let (cx, cy) = (width as f32 / 2., height as f32 / 2.);
+        let segments = (self.colors.len() - 1) as f32;
+
+        RgbaImage::from_fn(width, height, |x, y| {
+            let projection = (x as f32 + 0.5 - cx) * dx + (y as f32 + 0.5 - cy) * dy;
+            let t = (projection / length + 0.5).clamp(0., 1.) * segments;
+            let index = (t.floor() as usize).min(self.colors.len() - 2);
+            let (from, to) = (self.colors[index], self.colors[index + 1]);
+            let weight = t - index as f32;
+            Rgba(std::array::from_fn(|c| {
+                (from[c] as f32 * (1. - weight) + to[c] as f32 * weight).round() as u8
+            }))
+        })
+    }
+}
+
+/// How a background image is scaled to the output size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackgroundFit {
+    /// Fill the output, cropping the image
+    #[default]
+    Cover,
+    /// Fit the whole image inside the output, showing the fill around it
+    Contain,
+    /// Scale to the output size, ignoring the aspect ratio
+    Stretch,
+}
+
+/// Anchor of a background image that doesn't match the output's aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    /// Horizontal anchor from 0 (left) to 1 (right)
+    pub x: f32,
+    /// Vertical anchor from 0 (top) to 1 (bottom)
+    pub y: f32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self { x: 0.5, y: 0.5 }
+    }
+}
+
+impl FromStr for Position {
+    type Err = anyhow::Error;
+
+    /// Parses `center`, `top`, `bottom`, `left`, `right` or a combination such as `top-left`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut position = Self::default();
+        for part in s.trim().to_lowercase().split('-') {
+            match part {
+                "center" => {}
+                "top" => position.y = 0.,
+                "bottom" => position.y = 1.,
+                "left" => position.x = 0.,
+                "right" => position.x = 1.,
+                _ => {
+                    return Err(anyhow!(
+                        "Invalid position '{}'. Valid values are: center, top, bottom, left, right and combinations such as top-left",
+                        s
+                    ))
+                }
+            }
+        }
+        Ok(position)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackgroundImage {
+    pub image: Arc<RgbaImage>,
+    pub fit: BackgroundFit,
+    pub position: Position,
+}
+
+impl BackgroundImage {
+    /// Draws the image over `canvas` following its fit and position
+    fn draw(&self, canvas: &mut RgbaImage) {
+        let (width, height) = canvas.dimensions();
+        let (image_width, image_height) = self.image.dimensions();
+        let scale_x = width as f64 / image_width as f64;
+        let scale_y = height as f64 / image_height as f64;
+
+        match self.fit {
+            BackgroundFit::Stretch => {
+                let scaled = resize(&*self.image, width, height);
+                imageops::overlay(canvas, &scaled, 0, 0);
+            }
+            // Only the part of the image that ends up on the canvas is scaled,
+            // so extreme aspect ratios can't blow up the allocation
+            BackgroundFit::Cover => {
+                let scale = scale_x.max(scale_y);
+                let crop_width = ((width as f64 / scale).round() as u32).clamp(1, image_width);
+                let crop_height = ((height as f64 / scale).round() as u32).clamp(1, image_height);
+                let x = ((image_width - crop_width) as f32 * self.position.x).round() as u32;
+                let y = ((image_height - crop_height) as f32 * self.position.y).round() as u32;
+                let region = imageops::crop_imm(&*self.image, x, y, crop_width, crop_height);
+                let scaled = resize(&*region, width, height);
+                imageops::overlay(canvas, &scaled, 0, 0);
+            }
+            BackgroundFit::Contain => {
+                let scale = scale_x.min(scale_y);
+                let scaled_width = ((image_width as f64 * scale).round() as u32).clamp(1, width);
+                let scaled_height = ((image_height as f64 * scale).round() as u32).clamp(1, height);
+                let scaled = resize(&*self.image, scaled_width, scaled_height);
+                let x = ((width - scaled_width) as f32 * self.position.x).round() as i64;
+                let y = ((height - scaled_height) as f32 * self.position.y).round() as i64;
+                imageops::overlay(canvas, &scaled, x, y);
+            }
+        }
+    }
+}
+
+fn resize<I>(image: &I, width: u32, height: u32) -> RgbaImage
+where
+    I: GenericImageView<Pixel = Rgba<u8>>,
+{
+    imageops::resize(image, width, height, imageops::FilterType::Triangle)
+}
+
+/// Composites `foreground` onto `background`, returning an image of the same size
+pub fn composite(foreground: &RgbaImage, background: &Background) -> RgbaImage {
+    let (width, height) = foreground.dimensions();
+
+    let mut canvas = match &background.fill {
+        Some(Fill::Color(color)) => RgbaImage::from_pixel(width, height, *color),
+        Some(Fill::Gradient(gradient)) => gradient.render(width, height),
+        None => RgbaImage::from_pixel(width, height, DEFAULT_FILL),
+    };
+    if let Some(image) = &background.image {
+        image.draw(&mut canvas);
+    }
+
+    imageops::overlay(&mut canvas, foreground, 0, 0);
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    /// A 2x400 strip, red in the top half and blue in the bottom half
+    fn strip() -> Arc<RgbaImage> {
+        Arc::new(RgbaImage::from_fn(
+            2,
+            400,
+            |_, y| {
+                if y < 200 {
+                    RED
+                } else {
+                    BLUE
+                }
+            },
+        ))
+    }
+
+    fn cover(position: &str) -> Background {
+        Background {
+            fill: None,
+            image: Some(BackgroundImage {
+                image: strip(),
+                fit: BackgroundFit::Cover,
+                position: position.parse().unwrap(),
+            }),
+        }
+    }
+
+    #[test]
+    fn cover_scales_only_the_visible_region() {
+        // Covering 400x300 with the strip would scale it to 400x80000 as a whole
+        let foreground = RgbaImage::new(400, 300);
+
+        let top = composite(&foreground, &cover("top"));
+        assert_eq!(top.dimensions(), (400, 300));
+        assert!(top.pixels().all(|p| *p == RED));
+
+        let bottom = composite(&foreground, &cover("bottom"));
+        assert!(bottom.pixels().all(|p| *p == BLUE));
+    }
+
+    #[test]
+    fn contain_keeps_the_fill_around_the_image() {
+        let foreground = RgbaImage::new(400, 300);
+        let background = Background {
+            fill: Some(Fill::Color(Rgba([0, 255, 0, 255]))),
+            image: Some(BackgroundImage {
+                image: strip(),
+                fit: BackgroundFit::Contain,
+                position: Position::default(),
+            }),
+        };
+
+        let result = composite(&foreground, &background);
+        assert_eq!(result[(0, 150)], Rgba([0, 255, 0, 255]));
+        assert_eq!(result[(200, 0)], RED);
+        assert_eq!(result[(200, 299)], BLUE);
+    }
+}
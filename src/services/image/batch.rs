@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Result};
+use ndarray::{stack, Array2, Array3, ArrayViewD, Axis, CowArray};
+use ort::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout_at, Instant};
+
+use crate::config::{InferenceConfig, ModelProfile, TensorLayout};
+use crate::services::executor::BlockingExecutor;
+use crate::services::onnx::{PoolError, SessionPool};
+
+struct BatchItem {
+    input: Array3<f32>,
+    reply: oneshot::Sender<Result<Array2<f32>>>,
+}
+
+/// Spatial size of the tensors fed to a model
+#[derive(Debug, Clone, Copy)]
+pub enum InputSize {
+    /// Every image is resized to (height, width)
+    Fixed(usize, usize),
+    /// The model accepts any size: the longest side of the image is scaled to
+    /// `longest_side` and both sides are rounded to a multiple of `multiple`
+    Dynamic {
+        longest_side: usize,
+        multiple: usize,
+    },
+}
+
+impl InputSize {
+    /// Model input (height, width) for an image of the given size
+    pub fn for_image(&self, width: u32, height: u32) -> (usize, usize) {
+        match *self {
+            InputSize::Fixed(height, width) => (height, width),
+            InputSize::Dynamic {
+                longest_side,
+                multiple,
+            } => {
+                let scale = longest_side as f32 / width.max(height).max(1) as f32;
+                let round = |side: u32| {
+                    let steps = (side as f32 * scale / multiple as f32).round() as usize;
+                    steps.max(1) * multiple
+                };
+                (round(height), round(width))
+            }
+        }
+    }
+}
+
+/// Collects preprocessed tensors from concurrent requests and runs them
+/// through the model as a single batch. Models with a fixed batch dimension
+/// are always run one tensor at a time.
+pub struct Batcher {
+    sender: mpsc::Sender<BatchItem>,
+    input_size: InputSize,
+    max_batch_size: usize,
+}
+
+impl Batcher {
+    pub fn new(
+        session_pool: Arc<SessionPool>,
+        executor: Arc<BlockingExecutor>,
+        config: &InferenceConfig,
+        profile: &ModelProfile,
+    ) -> Result<Self> {
+        let dimensions = session_pool.input_dimensions();
+        let (batch, channels, height, width) = match (profile.layout, &dimensions[..]) {
+            (TensorLayout::Nchw, &[batch, channels, height, width]) => {
+                (batch, channels, height, width)
+            }
+            (TensorLayout::Nhwc, &[batch, height, width, channels]) => {
+                (batch, channels, height, width)
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Expected a 4-dimensional {:?} model input, got {:?}",
+                    profile.layout,
+                    dimensions
+                ))
+            }
+        };
+
+        if channels.is_some_and(|channels| channels != 3) {
+            return Err(anyhow!(
+                "Expected a 3-channel model input, got {:?}",
+                dimensions
+            ));
+        }
+
+        // Declared dimensions win, then the profile, then a size chosen per image
+        let input_size = match (height, width, profile.input_size) {
+            (Some(height), Some(width), _) => InputSize::Fixed(height, width),
+            (_, _, Some((height, width))) => InputSize::Fixed(height, width),
+            _ => InputSize::Dynamic {
+                longest_side: config.dynamic_longest_side,
+                multiple: config.dynamic_size_multiple.max(1),
+            },
+        };
+
+        // A fixed batch dimension (usually 1) means the model can't be fed more than one tensor
+        let max_batch_size = match batch {
+            None => config.max_batch_size.max(1),
+            Some(_) => 1,
+        };
+
+        let (sender, receiver) = mpsc::channel(config.queue_depth.max(1));
+        tokio::spawn(collect_batches(
+            receiver,
+            session_pool,
+            executor,
+            config.batch_window,
+            max_batch_size,
+            profile.output_index,
+        ));
+
+        Ok(Self {
+            sender,
+            input_size,
+            max_batch_size,
+        })
+    }
+
+    pub fn input_size(&self) -> InputSize {
+        self.input_size
+    }
+
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    /// Queues one tensor for inference and waits for its alpha mask
+    pub async fn infer(&self, input: Array3<f32>) -> Result<Array2<f32>> {
+        let (reply, response) = oneshot::channel();
+
+        self.sender
+            .try_send(BatchItem { input, reply })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => {
+                    PoolError::QueueFull(self.sender.max_capacity())
+                }
+                mpsc::error::TrySendError::Closed(_) => PoolError::Closed,
+            })?;
+
+        response
+            .await
+            .map_err(|_| anyhow!("Batch was dropped before completing"))?
+    }
+}
+
+async fn collect_batches(
+    mut receiver: mpsc::Receiver<BatchItem>,
+    session_pool: Arc<SessionPool>,
+    executor: Arc<BlockingExecutor>,
+    window: Duration,
+    max_batch_size: usize,
+    output_index: usize,
+) {
+    while let Some(first) = receiver.recv().await {
+        let deadline = Instant::now() + window;
+        let mut batch = vec![first];
+
+        while batch.len() < max_batch_size {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(item)) => batch.push(item),
+                // Window elapsed or all senders are gone
+                _ => break,
+            }
+        }
+
+        // Only tensors of the same shape can be stacked, which matters for dynamic input sizes
+        let mut groups: Vec<Vec<BatchItem>> = Vec::new();
+        for item in batch {
+            match groups
+                .iter_mut()
+                .find(|group| group[0].input.shape() == item.input.shape())
+            {
+                Some(group) => group.push(item),
+                None => groups.push(vec![item]),
+            }
+        }
+
+        // Run the batches in the background so the next one can start collecting
+        for group in groups {
+            tokio::spawn(run_batch(
+                group,
+                Arc::clone(&session_pool),
+                Arc::clone(&executor),
+                output_index,
+            ));
+        }
+    }
+}
+
+async fn run_batch(
+    batch: Vec<BatchItem>,
+    session_pool: Arc<SessionPool>,
+    executor: Arc<BlockingExecutor>,
+    output_index: usize,
+) {
+    let (inputs, replies): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|item| (item.input, item.reply))
+        .unzip();
+    let batch_size = inputs.len();
+
+    let result = match session_pool.acquire().await {
+        Ok(session) => executor
+            .run(move || -> Result<Vec<Array2<f32>>> {
+                let views = inputs.iter().map(|input| input.view()).collect::<Vec<_>>();
+                let input_tensor = CowArray::from(stack(Axis(0), &views)?.into_dyn());
+
+                let inputs = vec![Value::from_array(session.allocator(), &input_tensor)?];
+                let outputs = session.run(inputs)?;
+                let output_tensor = outputs
+                    .get(output_index)
+                    .ok_or_else(|| anyhow!("Model has no output {}", output_index))?
+                    .try_extract::<f32>()?;
+
+                let output = output_tensor.view();
+                split_alpha(&output, batch_size)
+            })
+            .await
+            .and_then(|result| result),
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(alphas) => {
+            for (reply, alpha) in replies.into_iter().zip(alphas) {
+                let _ = reply.send(Ok(alpha));
+            }
+        }
+        Err(e) => {
+            log::error!("Inference failed for batch of {}: {}", batch_size, e);
+            for reply in replies {
+                // Keep pool errors typed so callers can still tell overload apart from failure
+                let error = match e.downcast_ref::<PoolError>() {
+                    Some(pool_error) => pool_error.clone().into(),
+                    None => anyhow!("{}", e),
+                };
+                let _ = reply.send(Err(error));
+            }
+        }
+    }
+}
+
+/// Splits a batched model output (`[N, 1, H, W]`, `[N, H, W, 1]` or `[N, H, W]`)
+/// into one `H x W` alpha mask per input
+fn split_alpha(output: &ArrayViewD<f32>, batch_size: usize) -> Result<Vec<Array2<f32>>> {
+    let shape = output.shape();
+    let spatial = shape
+        .iter()
+        .skip(1)
+        .copied()
+        .filter(|&dim| dim != 1)
+        .collect::<Vec<_>>();
+
+    let (&[height, width], Some(&batch)) = (&spatial[..], shape.first()) else {
+        return Err(anyhow!("Unexpected model output shape {:?}", shape));
+    };
+    if batch != batch_size {
+        return Err(anyhow!(
+            "Unexpected model output shape {:?} for batch of {}",
+            shape,
+            batch_size
+        ));
+    }
+
+    output
+        .outer_iter()
+        .map(|alpha| {
+            let values = alpha.iter().copied().collect();
+            Ok(Array2::from_shape_vec((height, width), values)?)
+        })
+        .collect()
+}
@@ -0,0 +1,175 @@
+use image::{GrayImage, Rgba, RgbaImage};
+
+use super::refine::box_filter;
+
+/// Alpha at or above which a pixel counts as subject when measuring distances
+const SUBJECT_THRESHOLD: u8 = 128;
+
+/// Portrait-mode blur of everything behind the subject
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackgroundBlur {
+    /// Blur radius in pixels, defaults to 1/100 of the longest side
+    pub radius: Option<u32>,
+    /// Distance in pixels from the subject over which the blur ramps up to
+    /// the full radius, uniform blur when unset
+    pub falloff: Option<u32>,
+}
+
+/// Default blur radius for an image, about 1/100 of its longest side
+pub fn default_radius(width: u32, height: u32) -> u32 {
+    (width.max(height) / 100).max(2)
+}
+
+/// Keeps the subject sharp and blurs the rest of `image`, using `mask` to
+/// separate them. The result is opaque and the size of `image`.
+pub fn blur_background(image: &RgbaImage, mask: &GrayImage, blur: &BackgroundBlur) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let radius = blur.radius.unwrap_or_else(|| default_radius(width, height));
+
+    // The subject is left out of the blur so it doesn't bleed into its surroundings
+    let weights = mask
+        .pixels()
+        .map(|p| 1. - p[0] as f32 / 255.)
+        .collect::<Vec<_>>();
+    let full = masked_blur(image, &weights, radius);
+
+    // With a falloff, the blur strengthens with the distance from the subject
+    let (half, distances) = match blur.falloff {
+        Some(falloff) if falloff > 0 => (
+            Some(masked_blur(image, &weights, radius / 2)),
+            Some(
+                distance_from_subject(mask)
+                    .into_iter()
+                    .map(|d| (d / falloff as f32).min(1.))
+                    .collect::<Vec<_>>(),
+            ),
+        ),
+        _ => (None, None),
+    };
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let i = (y * width + x) as usize;
+        let original = image[(x, y)];
+        let background = match (&half, &distances) {
+            (Some(half), Some(distances)) => {
+                let t = distances[i];
+                if t < 0.5 {
+                    lerp(pixel(original), half[i], t * 2.)
+                } else {
+                    lerp(half[i], full[i], t * 2. - 1.)
+                }
+            }
+            _ => full[i],
+        };
+
+        let alpha = mask[(x, y)][0] as f32 / 255.;
+        let color = lerp(background, pixel(original), alpha);
+        Rgba([
+            color[0].round() as u8,
+            color[1].round() as u8,
+            color[2].round() as u8,
+            255,
+        ])
+    })
+}
+
+fn pixel(p: Rgba<u8>) -> [f32; 3] {
+    [p[0] as f32, p[1] as f32, p[2] as f32]
+}
+
+fn lerp(from: [f32; 3], to: [f32; 3], t: f32) -> [f32; 3] {
+    std::array::from_fn(|c| from[c] + (to[c] - from[c]) * t)
+}
+
+/// Blur of `image` weighted by `weights`, approximating a Gaussian with three
+/// box filter passes. Pixels whose window has no weight keep their colour.
+fn masked_blur(image: &RgbaImage, weights: &[f32], radius: u32) -> Vec<[f32; 3]> {
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    let radius = radius as usize;
+    let blur = |values: Vec<f32>| {
+        let once = box_filter(&values, w, h, radius);
+        let twice = box_filter(&once, w, h, radius);
+        box_filter(&twice, w, h, radius)
+    };
+
+    let weight = blur(weights.to_vec());
+    let channels: [Vec<f32>; 3] = std::array::from_fn(|c| {
+        blur(
+            image
+                .pixels()
+                .zip(weights)
+                .map(|(p, weight)| p[c] as f32 * weight)
+                .collect(),
+        )
+    });
+
+    image
+        .pixels()
+        .enumerate()
+        .map(|(i, p)| {
+            if weight[i] > 1e-4 {
+                std::array::from_fn(|c| channels[c][i] / weight[i])
+            } else {
+                pixel(*p)
+            }
+        })
+        .collect()
+}
+
+/// Approximate Euclidean distance of every pixel to the nearest subject pixel,
+/// using a two-pass chamfer transform
+fn distance_from_subject(mask: &GrayImage) -> Vec<f32> {
+    const DIAGONAL: f32 = std::f32::consts::SQRT_2;
+
+    let (w, h) = (mask.width() as usize, mask.height() as usize);
+    let mut distances = mask
+        .pixels()
+        .map(|p| {
+            if p[0] >= SUBJECT_THRESHOLD {
+                0.
+            } else {
+                f32::INFINITY
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Forward pass from the top left, then backward from the bottom right
+    for y in 0..h {
+        for x in 0..w {
+            let mut d = distances[y * w + x];
+            if x > 0 {
+                d = d.min(distances[y * w + x - 1] + 1.);
+            }
+            if y > 0 {
+                d = d.min(distances[(y - 1) * w + x] + 1.);
+                if x > 0 {
+                    d = d.min(distances[(y - 1) * w + x - 1] + DIAGONAL);
+                }
+                if x + 1 < w {
+                    d = d.min(distances[(y - 1) * w + x + 1] + DIAGONAL);
+                }
+            }
+            distances[y * w + x] = d;
+        }
+    }
+    for y in (0..h).rev() {
+        for x in (0..w).rev() {
+            let mut d = distances[y * w + x];
+            if x + 1 < w {
+                d = d.min(distances[y * w + x + 1] + 1.);
+            }
+            if y + 1 < h {
+                d = d.min(distances[(y + 1) * w + x] + 1.);
+                if x + 1 < w {
+                    d = d.min(distances[(y + 1) * w + x + 1] + DIAGONAL);
+                }
+                if x > 0 {
+                    d = d.min(distances[(y + 1) * w + x - 1] + DIAGONAL);
+                }
+            }
+            distances[y * w + x] = d;
+        }
+    }
+
+    distances
+}
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use image::{imageops, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Margin around the subject
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Padding {
+    Pixels(u32),
+    /// Percentage of the subject's longest side
+    Percent(f32),
+}
+
+impl FromStr for Padding {
+    type Err = anyhow::Error;
+
+    /// Parses `24` as pixels or `10%` as a percentage
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid padding '{}', expected pixels or a percentage", s);
+        match s.trim().strip_suffix('%') {
+            Some(percent) => {
+                let percent = percent.trim().parse::<f32>().map_err(|_| invalid())?;
+                if !percent.is_finite() || percent < 0. {
+                    return Err(invalid());
+                }
+                Ok(Padding::Percent(percent))
+            }
+            None => s.trim().parse().map(Padding::Pixels).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl Padding {
+    fn pixels(&self, width: u32, height: u32) -> u32 {
+        match *self {
+            Padding::Pixels(pixels) => pixels,
+            Padding::Percent(percent) => (width.max(height) as f32 * percent / 100.).round() as u32,
+        }
+    }
+}
+
+/// Width to height ratio of the canvas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AspectRatio(pub f32);
+
+impl FromStr for AspectRatio {
+    type Err = anyhow::Error;
+
+    /// Parses a ratio such as `1:1` or `4:5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid aspect ratio '{}', expected width:height", s);
+        let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+        let width = width.trim().parse::<f32>().map_err(|_| invalid())?;
+        let height = height.trim().parse::<f32>().map_err(|_| invalid())?;
+        if !(width.is_finite() && height.is_finite() && width > 0. && height > 0.) {
+            return Err(invalid());
+        }
+        Ok(AspectRatio(width / height))
+    }
+}
+
+/// Parses a canvas size given as `WIDTHxHEIGHT`, or a single number for a square
+pub fn parse_size(s: &str) -> Result<(u32, u32)> {
+    let invalid = || anyhow!("Invalid canvas size '{}', expected WIDTHxHEIGHT", s);
+    let (width, height) = match s.trim().split_once('x') {
+        Some((width, height)) => (width.trim().parse(), height.trim().parse()),
+        None => (s.trim().parse(), s.trim().parse()),
+    };
+    match (width, height) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Vertical placement of the subject on the canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    Center,
+    /// Subject rests on the bottom edge, inside the padding
+    Bottom,
+}
+
+/// Placement of the cropped subject on a canvas
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CanvasLayout {
+    pub padding: Option<Padding>,
+    /// Widen or heighten the canvas to this ratio
+    pub aspect: Option<AspectRatio>,
+    /// Exact output size, the subject is scaled to fit inside the padding
+    pub size: Option<(u32, u32)>,
+    pub align: Align,
+    /// Fill of the canvas around the subject, transparent by default
+    pub color: Option<Rgba<u8>>,
+}
+
+impl CanvasLayout {
+    pub fn is_enabled(&self) -> bool {
+        self.padding.is_some() || self.aspect.is_some() || self.size.is_some()
+    }
+
+    /// Number of pixels of the canvas for a subject of the given size
+    pub fn pixels(&self, width: u32, height: u32) -> u64 {
+        let (canvas_width, canvas_height) = match self.size {
+            Some((width, height)) => (width as f64, height as f64),
+            None => self.padded_size(width, height),
+        };
+        (canvas_width.round() * canvas_height.round()).min(u64::MAX as f64) as u64
+    }
+
+    /// Padded box around a subject of the given size, widened to the aspect ratio
+    fn padded_size(&self, width: u32, height: u32) -> (f64, f64) {
+        let padding = self
+            .padding
+            .map_or(0, |padding| padding.pixels(width, height));
+        let (mut canvas_width, mut canvas_height) = (
+            width as f64 + 2. * padding as f64,
+            height as f64 + 2. * padding as f64,
+        );
+        if let Some(AspectRatio(ratio)) = self.aspect {
+            let ratio = ratio as f64;
+            if canvas_width / canvas_height < ratio {
+                canvas_width = canvas_height * ratio;
+            } else {
+                canvas_height = canvas_width / ratio;
+            }
+        }
+        (canvas_width, canvas_height)
+    }
+}
+
+/// Places `subject` on a canvas following `layout`
+pub fn place_on_canvas(subject: RgbaImage, layout: &CanvasLayout) -> RgbaImage {
+    let (width, height) = subject.dimensions();
+    let padding = layout
+        .padding
+        .map_or(0, |padding| padding.pixels(width, height));
+
+    let (canvas_width, canvas_height) = layout.padded_size(width, height);
+
+    // A fixed canvas size scales everything to fit
+    let (subject, padding, canvas_width, canvas_height) = match layout.size {
+        Some((target_width, target_height)) => {
+            let scale = f64::min(
+                target_width as f64 / canvas_width,
+                target_height as f64 / canvas_height,
+            );
+            let scaled = imageops::resize(
+                &subject,
+                ((width as f64 * scale).round() as u32).clamp(1, target_width),
+                ((height as f64 * scale).round() as u32).clamp(1, target_height),
+                imageops::FilterType::Triangle,
+            );
+            let padding = (padding as f64 * scale).round() as u32;
+            (scaled, padding, target_width, target_height)
+        }
+        None => (
+            subject,
+            padding,
+            canvas_width.round() as u32,
+            canvas_height.round() as u32,
+        ),
+    };
+
+    let (subject_width, subject_height) = subject.dimensions();
+    let x = canvas_width.saturating_sub(subject_width) / 2;
+    let y = match layout.align {
+        Align::Center => canvas_height.saturating_sub(subject_height) / 2,
+        Align::Bottom => canvas_height.saturating_sub(subject_height + padding),
+    };
+
+    let color = layout.color.unwrap_or(Rgba([0, 0, 0, 0]));
+    let mut canvas = RgbaImage::from_pixel(canvas_width, canvas_height, color);
+    imageops::overlay(&mut canvas, &subject, x as i64, y as i64);
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_padding() {
+        assert_eq!("24".parse::<Padding>().unwrap(), Padding::Pixels(24));
+        assert_eq!(" 10% ".parse::<Padding>().unwrap(), Padding::Percent(10.));
+        assert_eq!("2.5%".parse::<Padding>().unwrap(), Padding::Percent(2.5));
+        assert!("-3".parse::<Padding>().is_err());
+        assert!("-3%".parse::<Padding>().is_err());
+        assert!("NaN%".parse::<Padding>().is_err());
+        assert!("ten".parse::<Padding>().is_err());
+    }
+
+    #[test]
+    fn parses_aspect_ratio() {
+        assert_eq!("1:1".parse::<AspectRatio>().unwrap(), AspectRatio(1.));
+        assert_eq!("4 : 5".parse::<AspectRatio>().unwrap(), AspectRatio(0.8));
+        assert!("16".parse::<AspectRatio>().is_err());
+        assert!("0:1".parse::<AspectRatio>().is_err());
+        assert!("1:-2".parse::<AspectRatio>().is_err());
+        assert!("inf:1".parse::<AspectRatio>().is_err());
+    }
+
+    #[test]
+    fn parses_size() {
+        assert_eq!(parse_size("800x600").unwrap(), (800, 600));
+        assert_eq!(parse_size(" 512 ").unwrap(), (512, 512));
+        assert!(parse_size("0x600").is_err());
+        assert!(parse_size("800x").is_err());
+        assert!(parse_size("800*600").is_err());
+    }
+
+    #[test]
+    fn counts_canvas_pixels() {
+        let padded = CanvasLayout {
+            padding: Some(Padding::Pixels(10)),
+            ..Default::default()
+        };
+        assert_eq!(padded.pixels(100, 50), 120 * 70);
+
+        let square = CanvasLayout {
+            aspect: Some(AspectRatio(1.)),
+            ..Default::default()
+        };
+        assert_eq!(square.pixels(100, 50), 100 * 100);
+
+        let sized = CanvasLayout {
+            padding: Some(Padding::Percent(50.)),
+            size: Some((300, 200)),
+            ..Default::default()
+        };
+        assert_eq!(sized.pixels(100, 50), 300 * 200);
+
+        let huge = CanvasLayout {
+            padding: Some(Padding::Pixels(u32::MAX)),
+            ..Default::default()
+        };
+        assert!(huge.pixels(100, 50) > 1 << 60);
+    }
+
+    #[test]
+    fn places_subject_on_canvas() {
+        let subject = RgbaImage::from_pixel(4, 2, Rgba([255, 0, 0, 255]));
+        let layout = CanvasLayout {
+            padding: Some(Padding::Pixels(1)),
+            align: Align::Bottom,
+            ..Default::default()
+        };
+
+        let canvas = place_on_canvas(subject, &layout);
+
+        assert_eq!(canvas.dimensions(), (6, 4));
+        assert_eq!(canvas[(1, 2)], Rgba([255, 0, 0, 255]));
+        assert_eq!(canvas[(0, 0)], Rgba([0, 0, 0, 0]));
+    }
+}
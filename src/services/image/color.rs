@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Result};
+use image::Rgba;
+
+/// Parses a colour given as hex (`#rgb`, `#rrggbb`, `#rrggbbaa`, the `#` is
+/// optional), `rgb(r, g, b)`, `rgba(r, g, b, a)` with `a` in `[0, 1]`, or one
+/// of `white`, `black` and `transparent`.
+pub fn parse_color(s: &str) -> Result<Rgba<u8>> {
+    let value = s.trim().to_lowercase();
+    let invalid = || anyhow!("Invalid colour '{}'", s);
+
+    match value.as_str() {
+        "white" => return Ok(Rgba([255, 255, 255, 255])),
+        "black" => return Ok(Rgba([0, 0, 0, 255])),
+        "transparent" => return Ok(Rgba([0, 0, 0, 0])),
+        _ => {}
+    }
+
+    if let Some(args) = value
+        .strip_prefix("rgba(")
+        .or_else(|| value.strip_prefix("rgb("))
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let parts = args.split(',').map(str::trim).collect::<Vec<_>>();
+        let channel = |part: &str| part.parse::<u8>().map_err(|_| invalid());
+        let alpha = match parts.len() {
+            3 => 255,
+            4 => {
+                let alpha = parts[3].parse::<f32>().map_err(|_| invalid())?;
+                if !(0. ..=1.).contains(&alpha) {
+                    return Err(invalid());
+                }
+                (alpha * 255.).round() as u8
+            }
+            _ => return Err(invalid()),
+        };
+        return Ok(Rgba([
+            channel(parts[0])?,
+            channel(parts[1])?,
+            channel(parts[2])?,
+            alpha,
+        ]));
+    }
+
+    let hex = value.strip_prefix('#').unwrap_or(&value);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    let nibble = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).map(|v| v * 17);
+
+    match hex.len() {
+        3 => Ok(Rgba([nibble(0)?, nibble(1)?, nibble(2)?, 255])),
+        6 => Ok(Rgba([byte(0)?, byte(2)?, byte(4)?, 255])),
+        8 => Ok(Rgba([byte(0)?, byte(2)?, byte(4)?, byte(6)?])),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(parse_color("#fff").unwrap(), Rgba([255, 255, 255, 255]));
+        assert_eq!(parse_color("00ff80").unwrap(), Rgba([0, 255, 128, 255]));
+        assert_eq!(parse_color("#FF000080").unwrap(), Rgba([255, 0, 0, 128]));
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#ggg").is_err());
+    }
+
+    #[test]
+    fn parses_rgb_functions() {
+        assert_eq!(
+            parse_color("rgb(10, 20, 30)").unwrap(),
+            Rgba([10, 20, 30, 255])
+        );
+        assert_eq!(
+            parse_color("RGBA(10,20,30,0.5)").unwrap(),
+            Rgba([10, 20, 30, 128])
+        );
+        assert!(parse_color("rgb(256, 0, 0)").is_err());
+        assert!(parse_color("rgba(0, 0, 0, 2)").is_err());
+        assert!(parse_color("rgb(0, 0)").is_err());
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(parse_color(" White ").unwrap(), Rgba([255, 255, 255, 255]));
+        assert_eq!(parse_color("transparent").unwrap(), Rgba([0, 0, 0, 0]));
+        assert!(parse_color("chartreuse").is_err());
+    }
+}
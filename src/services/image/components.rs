@@ -0,0 +1,298 @@
+use image::{GrayImage, Luma};
+use std::collections::VecDeque;
+
+/// Alpha at or above which a pixel counts as part of the subject
+const SOLID_THRESHOLD: u8 = 128;
+
+/// Connected-component clean-up of the mask
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MaskCleanup {
+    /// Drop subject components with fewer pixels than this
+    pub min_island_area: Option<u32>,
+    /// Fill background regions fully enclosed by the subject
+    pub fill_holes: bool,
+    /// Only fill holes up to this many pixels, all enclosed holes otherwise
+    pub max_hole_area: Option<u32>,
+    /// Keep only the N largest subject components
+    pub keep_largest: Option<u32>,
+}
+
+impl MaskCleanup {
+    pub fn is_enabled(&self) -> bool {
+        self.fill_holes || self.removes_islands()
+    }
+
+    fn removes_islands(&self) -> bool {
+        self.min_island_area.is_some() || self.keep_largest.is_some()
+    }
+}
+
+/// Connected components of a binary image, labelled from 1 with 0 meaning
+/// the pixel is not part of any component
+pub struct Components {
+    pub labels: Vec<u32>,
+    /// Pixel count of each component, indexed by `label - 1`
+    pub areas: Vec<usize>,
+}
+
+impl Components {
+    /// Labels the components of pixels for which `inside` holds, with
+    /// 8-connectivity when `diagonal` is set and 4-connectivity otherwise
+    pub fn label<F>(mask: &GrayImage, inside: F, diagonal: bool) -> Self
+    where
+        F: Fn(u8) -> bool,
+    {
+        let (w, h) = (mask.width() as usize, mask.height() as usize);
+        let inside = mask.pixels().map(|p| inside(p[0])).collect::<Vec<_>>();
+        let mut labels = vec![0; w * h];
+        let mut areas = Vec::new();
+        let mut stack = Vec::new();
+
+        for start in 0..w * h {
+            if !inside[start] || labels[start] != 0 {
+                continue;
+            }
+
+            let label = areas.len() as u32 + 1;
+            let mut area = 0;
+            labels[start] = label;
+            stack.push(start);
+            while let Some(i) = stack.pop() {
+                area += 1;
+                for j in neighbours(i, w, h, diagonal) {
+                    if inside[j] && labels[j] == 0 {
+                        labels[j] = label;
+                        stack.push(j);
+                    }
+                }
+            }
+            areas.push(area);
+        }
+
+        Self { labels, areas }
+    }
+
+    /// Pixel count of the component a pixel belongs to, 0 outside components
+    pub fn area_at(&self, i: usize) -> usize {
+        match self.labels[i] {
+            0 => 0,
+            label => self.areas[label as usize - 1],
+        }
+    }
+}
+
+/// Applies hole filling, then island removal, to `mask` in place
+pub fn clean_mask(mask: &mut GrayImage, cleanup: &MaskCleanup) {
+    if cleanup.fill_holes {
+        fill_holes(mask, cleanup.max_hole_area);
+    }
+    if cleanup.removes_islands() {
+        remove_islands(mask, cleanup.min_island_area, cleanup.keep_largest);
+    }
+}
+
+fn fill_holes(mask: &mut GrayImage, max_area: Option<u32>) {
+    let (w, h) = (mask.width() as usize, mask.height() as usize);
+    let background = Components::label(mask, |a| a < SOLID_THRESHOLD, false);
+
+    // Background regions touching the border aren't enclosed
+    let mut enclosed = vec![true; background.areas.len()];
+    for (i, &label) in background.labels.iter().enumerate() {
+        let (x, y) = (i % w, i / w);
+        if label != 0 && (x == 0 || y == 0 || x == w - 1 || y == h - 1) {
+            enclosed[label as usize - 1] = false;
+        }
+    }
+
+    for (i, pixel) in mask.pixels_mut().enumerate() {
+        let label = background.labels[i];
+        if label != 0
+            && enclosed[label as usize - 1]
+            && max_area.is_none_or(|max| background.area_at(i) <= max as usize)
+        {
+            *pixel = Luma([255]);
+        }
+    }
+}
+
+fn remove_islands(mask: &mut GrayImage, min_area: Option<u32>, keep_largest: Option<u32>) {
+    let (w, h) = (mask.width() as usize, mask.height() as usize);
+    let mut subject = Components::label(mask, |a| a >= SOLID_THRESHOLD, true);
+
+    let mut kept = subject
+        .areas
+        .iter()
+        .map(|&area| min_area.is_none_or(|min| area >= min as usize))
+        .collect::<Vec<_>>();
+    if let Some(n) = keep_largest {
+        let mut by_area = (0..subject.areas.len())
+            .filter(|&c| kept[c])
+            .collect::<Vec<_>>();
+        by_area.sort_by_key(|&c| std::cmp::Reverse(subject.areas[c]));
+        for &c in by_area.iter().skip(n as usize) {
+            kept[c] = false;
+        }
+    }
+
+    // Soft edge pixels belong to the solid component they are attached to
+    let mut queue = (0..w * h)
+        .filter(|&i| subject.labels[i] != 0)
+        .collect::<VecDeque<_>>();
+    while let Some(i) = queue.pop_front() {
+        for j in neighbours(i, w, h, true) {
+            if subject.labels[j] == 0 && mask.as_raw()[j] > 0 {
+                subject.labels[j] = subject.labels[i];
+                queue.push_back(j);
+            }
+        }
+    }
+
+    // Faint pixels not attached to any component are dropped as well
+    for (i, pixel) in mask.pixels_mut().enumerate() {
+        let label = subject.labels[i];
+        if label == 0 || !kept[label as usize - 1] {
+            *pixel = Luma([0]);
+        }
+    }
+}
+
+fn neighbours(i: usize, w: usize, h: usize, diagonal: bool) -> impl Iterator<Item = usize> {
+    const OFFSETS: [(isize, isize); 8] = [
+        (-1, 0),
+        (1, 0),
+        (0, -1),
+        (0, 1),
+        (-1, -1),
+        (1, -1),
+        (-1, 1),
+        (1, 1),
+    ];
+
+    let (x, y) = ((i % w) as isize, (i / w) as isize);
+    let count = if diagonal { 8 } else { 4 };
+    OFFSETS[..count].iter().filter_map(move |&(dx, dy)| {
+        let (x2, y2) = (x + dx, y + dy);
+        (x2 >= 0 && y2 >= 0 && x2 < w as isize && y2 < h as isize)
+            .then(|| y2 as usize * w + x2 as usize)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a mask from rows of `#` (solid), `+` (soft edge) and `.` (empty)
+    fn mask(rows: &[&str]) -> GrayImage {
+        GrayImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            Luma([match rows[y as usize].as_bytes()[x as usize] {
+                b'#' => 255,
+                b'+' => 100,
+                _ => 0,
+            }])
+        })
+    }
+
+    fn rows(mask: &GrayImage) -> Vec<String> {
+        mask.rows()
+            .map(|row| {
+                row.map(|p| match p[0] {
+                    255 => '#',
+                    0 => '.',
+                    _ => '+',
+                })
+                .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn labels_with_and_without_diagonals() {
+        let diagonal = mask(&["#..", ".#.", "..#"]);
+
+        let four = Components::label(&diagonal, |a| a >= SOLID_THRESHOLD, false);
+        assert_eq!(four.areas, [1, 1, 1]);
+
+        let eight = Components::label(&diagonal, |a| a >= SOLID_THRESHOLD, true);
+        assert_eq!(eight.areas, [3]);
+        assert_eq!(eight.area_at(4), 3);
+        assert_eq!(eight.area_at(1), 0);
+    }
+
+    #[test]
+    fn fills_only_enclosed_holes() {
+        let mut ring = mask(&[
+            "........", //
+            ".#####..", //
+            ".#...#..", //
+            ".#...#..", //
+            ".#####..", //
+            "........", //
+        ]);
+        let original = ring.clone();
+
+        fill_holes(&mut ring, None);
+        assert_eq!(
+            rows(&ring),
+            [
+                "........", //
+                ".#####..", //
+                ".#####..", //
+                ".#####..", //
+                ".#####..", //
+                "........", //
+            ]
+        );
+
+        // The hole has 6 pixels
+        let mut small_holes_only = original.clone();
+        fill_holes(&mut small_holes_only, Some(5));
+        assert_eq!(small_holes_only, original);
+
+        // A gap to the border opens the hole
+        let mut open = mask(&[
+            ".#####", //
+            ".#...#", //
+            ".#...#", //
+            ".##.##", //
+        ]);
+        let before = open.clone();
+        fill_holes(&mut open, None);
+        assert_eq!(open, before);
+    }
+
+    #[test]
+    fn keeps_largest_components() {
+        let blobs = mask(&[
+            "###.....#", //
+            "###..##..", //
+            "###..##..", //
+        ]);
+
+        let mut largest = blobs.clone();
+        remove_islands(&mut largest, None, Some(1));
+        assert_eq!(rows(&largest), ["###......", "###......", "###......"]);
+
+        let mut two = blobs.clone();
+        remove_islands(&mut two, None, Some(2));
+        assert_eq!(rows(&two), ["###......", "###..##..", "###..##.."]);
+
+        // The speck goes with a minimum area, the smaller blob stays
+        let mut no_specks = blobs.clone();
+        remove_islands(&mut no_specks, Some(2), None);
+        assert_eq!(rows(&no_specks), rows(&two));
+    }
+
+    #[test]
+    fn soft_edges_follow_their_component() {
+        let mut edged = mask(&[
+            "+##+..+", //
+            "+##+...", //
+            "......#", //
+        ]);
+
+        remove_islands(&mut edged, Some(2), None);
+        // The edge of the kept blob stays, the speck and the faint pixel
+        // attached to nothing solid go
+        assert_eq!(rows(&edged), ["+##+...", "+##+...", "......."]);
+    }
+}
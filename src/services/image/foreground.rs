@@ -0,0 +1,125 @@
+use image::RgbaImage;
+
+/// Weight keeping neighbouring colour estimates close in flat alpha regions
+const REGULARIZATION: f32 = 1e-5;
+/// Weight of the alpha gradient in the smoothness term
+const GRADIENT_WEIGHT: f32 = 1.;
+/// Levels at or below this size are cheap, so they get more iterations
+const SMALL_SIZE: usize = 32;
+const SMALL_ITERATIONS: usize = 10;
+const BIG_ITERATIONS: usize = 2;
+
+/// Replaces the colour of partially transparent pixels with an estimate of
+/// the foreground colour, removing background spill at the subject's edges.
+///
+/// Uses multi-level foreground estimation (Germer et al.): foreground and
+/// background colours are solved coarse-to-fine so that each pixel satisfies
+/// `image = alpha * F + (1 - alpha) * B` while staying smooth along the mask.
+pub fn decontaminate(image: &mut RgbaImage) {
+    let (width, height) = image.dimensions();
+    let (w0, h0) = (width as usize, height as usize);
+    if w0 == 0 || h0 == 0 {
+        return;
+    }
+
+    let colors = image
+        .pixels()
+        .map(|p| [p[0] as f32 / 255., p[1] as f32 / 255., p[2] as f32 / 255.])
+        .collect::<Vec<_>>();
+    let alphas = image
+        .pixels()
+        .map(|p| p[3] as f32 / 255.)
+        .collect::<Vec<_>>();
+
+    let levels = (w0.max(h0) as f32).log2().ceil().max(1.) as i32;
+    let (mut foreground, mut background) = (vec![[0.; 3]], vec![[0.; 3]]);
+    let (mut prev_w, mut prev_h) = (1, 1);
+
+    for level in 0..=levels {
+        let exponent = level as f32 / levels as f32;
+        let w = ((w0 as f32).powf(exponent).round() as usize).max(1);
+        let h = ((h0 as f32).powf(exponent).round() as usize).max(1);
+
+        let color = resize_nearest(&colors, w0, h0, w, h);
+        let alpha = resize_nearest(&alphas, w0, h0, w, h);
+        foreground = resize_nearest(&foreground, prev_w, prev_h, w, h);
+        background = resize_nearest(&background, prev_w, prev_h, w, h);
+
+        let iterations = if w <= SMALL_SIZE && h <= SMALL_SIZE {
+            SMALL_ITERATIONS
+        } else {
+            BIG_ITERATIONS
+        };
+        for _ in 0..iterations {
+            update(&color, &alpha, &mut foreground, &mut background, w, h);
+        }
+
+        (prev_w, prev_h) = (w, h);
+    }
+
+    for (pixel, estimate) in image.pixels_mut().zip(&foreground) {
+        if pixel[3] > 0 && pixel[3] < 255 {
+            for c in 0..3 {
+                pixel[c] = (estimate[c] * 255.).round() as u8;
+            }
+        }
+    }
+}
+
+/// One Gauss-Seidel sweep over all pixels of a level
+fn update(
+    color: &[[f32; 3]],
+    alpha: &[f32],
+    foreground: &mut [[f32; 3]],
+    background: &mut [[f32; 3]],
+    w: usize,
+    h: usize,
+) {
+    const NEIGHBOURS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            let a0 = alpha[i];
+            let a1 = 1. - a0;
+
+            let (mut a00, a01, mut a11) = (a0 * a0, a0 * a1, a1 * a1);
+            let mut b0 = color[i].map(|c| a0 * c);
+            let mut b1 = color[i].map(|c| a1 * c);
+
+            for (dx, dy) in NEIGHBOURS {
+                let x2 = (x as isize + dx).clamp(0, w as isize - 1) as usize;
+                let y2 = (y as isize + dy).clamp(0, h as isize - 1) as usize;
+                let j = y2 * w + x2;
+
+                let weight = REGULARIZATION + GRADIENT_WEIGHT * (a0 - alpha[j]).abs();
+                a00 += weight;
+                a11 += weight;
+                for c in 0..3 {
+                    b0[c] += weight * foreground[j][c];
+                    b1[c] += weight * background[j][c];
+                }
+            }
+
+            let inverse_determinant = 1. / (a00 * a11 - a01 * a01);
+            for c in 0..3 {
+                let f = inverse_determinant * (a11 * b0[c] - a01 * b1[c]);
+                let b = inverse_determinant * (a00 * b1[c] - a01 * b0[c]);
+                foreground[i][c] = f.clamp(0., 1.);
+                background[i][c] = b.clamp(0., 1.);
+            }
+        }
+    }
+}
+
+fn resize_nearest<T: Copy>(values: &[T], w0: usize, h0: usize, w: usize, h: usize) -> Vec<T> {
+    let mut output = Vec::with_capacity(w * h);
+    for y in 0..h {
+        let y0 = (y * h0 / h).min(h0 - 1);
+        for x in 0..w {
+            let x0 = (x * w0 / w).min(w0 - 1);
+            output.push(values[y0 * w0 + x0]);
+        }
+    }
+    output
+}
@@ -0,0 +1,460 @@
+use image::{GrayImage, Luma, RgbaImage};
+use std::collections::HashMap;
+
+use super::refine::box_filter;
+
+pub const TRIMAP_BACKGROUND: u8 = 0;
+pub const TRIMAP_UNKNOWN: u8 = 128;
+pub const TRIMAP_FOREGROUND: u8 = 255;
+
+/// Alpha at or above which a pixel is considered confidently foreground
+const FOREGROUND_THRESHOLD: u8 = 240;
+/// Alpha at or below which a pixel is considered confidently background
+const BACKGROUND_THRESHOLD: u8 = 15;
+
+/// Regularization of the closed-form matting Laplacian
+const MATTING_EPS: f64 = 1e-7;
+const MAX_ITERATIONS: usize = 300;
+const TOLERANCE: f64 = 1e-4;
+
+/// Widest unknown band a request may ask for
+pub const MAX_BAND: u32 = 64;
+
+/// Default width of the unknown band for an image, about 1/200 of its longest side
+pub fn default_band(width: u32, height: u32) -> u32 {
+    (width.max(height) / 200).clamp(3, MAX_BAND)
+}
+
+/// Derives a trimap from `mask`: confident regions are eroded by `band`
+/// pixels and everything left in between becomes unknown.
+pub fn trimap(mask: &GrayImage, band: u32) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    let indicator = |keep: fn(u8) -> bool| {
+        mask.pixels()
+            .map(|p| if keep(p[0]) { 1. } else { 0. })
+            .collect::<Vec<f32>>()
+    };
+
+    // A pixel survives erosion when its whole window lies inside the region
+    let foreground = box_filter(
+        &indicator(|a| a >= FOREGROUND_THRESHOLD),
+        w,
+        h,
+        band as usize,
+    );
+    let background = box_filter(
+        &indicator(|a| a <= BACKGROUND_THRESHOLD),
+        w,
+        h,
+        band as usize,
+    );
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let i = y as usize * w + x as usize;
+        Luma([if foreground[i] > 0.999 {
+            TRIMAP_FOREGROUND
+        } else if background[i] > 0.999 {
+            TRIMAP_BACKGROUND
+        } else {
+            TRIMAP_UNKNOWN
+        }])
+    })
+}
+
+/// Trimap-based alpha matting: keeps the confident regions of `mask` and
+/// solves for fractional alpha in the unknown band around the subject's edge
+/// with closed-form matting (Levin et al.), using `image` colours.
+pub fn alpha_matting(image: &RgbaImage, mask: &GrayImage, band: u32) -> GrayImage {
+    let trimap = trimap(mask, band);
+    solve_closed_form(image, &trimap, mask)
+}
+
+/// Solves for alpha in the unknown region of `trimap`, starting from `initial`
+pub fn solve_closed_form(image: &RgbaImage, trimap: &GrayImage, initial: &GrayImage) -> GrayImage {
+    let mut output = GrayImage::from_fn(trimap.width(), trimap.height(), |x, y| {
+        Luma([match trimap[(x, y)][0] {
+            TRIMAP_UNKNOWN => initial[(x, y)][0],
+            value => value,
+        }])
+    });
+
+    let (positions, alpha) = solve_unknowns(image, trimap, &output);
+    for (&(x, y), alpha) in positions.iter().zip(alpha) {
+        output[(x, y)] = Luma([(alpha.clamp(0., 1.) * 255.).round() as u8]);
+    }
+
+    output
+}
+
+/// Solves for the unknown pixels of `trimap` and returns their positions with
+/// the unclamped alpha. `alpha` holds the known alpha and the initial guess
+/// for the unknowns. Only the unknowns and the pixels sharing a window with
+/// them are indexed, so memory follows the size of the band, not the image.
+fn solve_unknowns(
+    image: &RgbaImage,
+    trimap: &GrayImage,
+    alpha: &GrayImage,
+) -> (Vec<(u32, u32)>, Vec<f64>) {
+    let (width, height) = trimap.dimensions();
+
+    // Nodes of the system: the unknowns first, then the known pixels around them
+    let mut positions = trimap
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] == TRIMAP_UNKNOWN)
+        .map(|(x, y, _)| (x, y))
+        .collect::<Vec<_>>();
+    let unknowns = positions.len();
+    if unknowns == 0 {
+        return (positions, Vec::new());
+    }
+
+    let mut index = positions
+        .iter()
+        .enumerate()
+        .map(|(n, &position)| (position, n as u32))
+        .collect::<HashMap<_, _>>();
+
+    // Centres of the 3x3 windows touching an unknown pixel
+    let mut centers = Vec::with_capacity(unknowns * 3);
+    for &(x, y) in &positions {
+        for cy in y.saturating_sub(1)..=y + 1 {
+            for cx in x.saturating_sub(1)..=x + 1 {
+                if cx >= 1 && cy >= 1 && cx + 1 < width && cy + 1 < height {
+                    centers.push((cy, cx));
+                }
+            }
+        }
+    }
+    centers.sort_unstable();
+    centers.dedup();
+
+    let windows = centers
+        .into_iter()
+        .map(|(cy, cx)| {
+            std::array::from_fn(|k| {
+                let position = (cx + k as u32 % 3 - 1, cy + k as u32 / 3 - 1);
+                *index.entry(position).or_insert_with(|| {
+                    positions.push(position);
+                    (positions.len() - 1) as u32
+                })
+            })
+        })
+        .collect::<Vec<[u32; 9]>>();
+    drop(index);
+
+    let colors = positions
+        .iter()
+        .map(|&(x, y)| {
+            let p = image[(x, y)];
+            [p[0] as f64 / 255., p[1] as f64 / 255., p[2] as f64 / 255.]
+        })
+        .collect::<Vec<_>>();
+    let laplacian = MattingLaplacian::new(windows, colors);
+
+    // Right-hand side: -L * known alpha, restricted to the unknowns
+    let known = positions
+        .iter()
+        .enumerate()
+        .map(|(n, &(x, y))| {
+            if n < unknowns {
+                0.
+            } else {
+                alpha[(x, y)][0] as f64 / 255.
+            }
+        })
+        .collect::<Vec<_>>();
+    let b = laplacian.apply(&known)[..unknowns]
+        .iter()
+        .map(|v| -v)
+        .collect::<Vec<_>>();
+
+    // Warm start from the model's prediction
+    let x0 = positions[..unknowns]
+        .iter()
+        .map(|&(x, y)| alpha[(x, y)][0] as f64 / 255.)
+        .collect::<Vec<_>>();
+
+    let nodes = positions.len();
+    let operator = |x: &[f64]| {
+        let mut full = x.to_vec();
+        full.resize(nodes, 0.);
+        let mut result = laplacian.apply(&full);
+        result.truncate(unknowns);
+        result
+    };
+    let mut diagonal = laplacian.diagonal();
+    diagonal.truncate(unknowns);
+    let solution = conjugate_gradient(operator, &diagonal, &b, x0);
+
+    positions.truncate(unknowns);
+    (positions, solution)
+}
+
+/// Colour mean and inverse regularized colour covariance of a 3x3 window
+struct Window {
+    nodes: [u32; 9],
+    mean: [f64; 3],
+    inverse: [[f64; 3]; 3],
+}
+
+/// Matrix-free matting Laplacian over the 3x3 windows touching unknown pixels
+struct MattingLaplacian {
+    windows: Vec<Window>,
+    colors: Vec<[f64; 3]>,
+}
+
+impl MattingLaplacian {
+    fn new(windows: Vec<[u32; 9]>, colors: Vec<[f64; 3]>) -> Self {
+        let windows = windows
+            .into_iter()
+            .map(|nodes| {
+                let mut mean = [0.; 3];
+                for &j in &nodes {
+                    for c in 0..3 {
+                        mean[c] += colors[j as usize][c] / 9.;
+                    }
+                }
+
+                let mut covariance = [[0.; 3]; 3];
+                for &j in &nodes {
+                    let color = colors[j as usize];
+                    for r in 0..3 {
+                        for c in 0..3 {
+                            covariance[r][c] += (color[r] - mean[r]) * (color[c] - mean[c]) / 9.;
+                        }
+                    }
+                }
+                for (d, row) in covariance.iter_mut().enumerate() {
+                    row[d] += MATTING_EPS / 9.;
+                }
+
+                Window {
+                    nodes,
+                    mean,
+                    inverse: invert3(&covariance),
+                }
+            })
+            .collect();
+
+        Self { windows, colors }
+    }
+
+    /// Diagonal of `L`, used to precondition the solver
+    fn diagonal(&self) -> Vec<f64> {
+        let mut diagonal = vec![0.; self.colors.len()];
+
+        for window in &self.windows {
+            for &i in &window.nodes {
+                let i = i as usize;
+                let d = std::array::from_fn(|c| self.colors[i][c] - window.mean[c]);
+                let v = mat_vec(&window.inverse, &d);
+                diagonal[i] += 1. - (1. + d[0] * v[0] + d[1] * v[1] + d[2] * v[2]) / 9.;
+            }
+        }
+
+        diagonal
+    }
+
+    /// Computes `L * x` for a vector over the nodes
+    fn apply(&self, x: &[f64]) -> Vec<f64> {
+        let mut result = vec![0.; x.len()];
+
+        for window in &self.windows {
+            let mean = window.mean;
+
+            let mut sum = 0.;
+            let mut weighted = [0.; 3];
+            for &j in &window.nodes {
+                let j = j as usize;
+                sum += x[j];
+                for c in 0..3 {
+                    weighted[c] += (self.colors[j][c] - mean[c]) * x[j];
+                }
+            }
+            let v = mat_vec(&window.inverse, &weighted);
+
+            for &i in &window.nodes {
+                let i = i as usize;
+                let d = [
+                    self.colors[i][0] - mean[0],
+                    self.colors[i][1] - mean[1],
+                    self.colors[i][2] - mean[2],
+                ];
+                let affinity = sum + d[0] * v[0] + d[1] * v[1] + d[2] * v[2];
+                result[i] += x[i] - affinity / 9.;
+            }
+        }
+
+        result
+    }
+}
+
+fn mat_vec(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+fn invert3(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+    let determinant =
+        m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+
+    adjugate.map(|row| row.map(|value| value / determinant))
+}
+
+/// Solves `A x = b` for a symmetric positive semi-definite `A` given as a
+/// function, preconditioned by its `diagonal`
+fn conjugate_gradient<F>(operator: F, diagonal: &[f64], b: &[f64], mut x: Vec<f64>) -> Vec<f64>
+where
+    F: Fn(&[f64]) -> Vec<f64>,
+{
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+    let precondition = |r: &[f64]| {
+        r.iter()
+            .zip(diagonal)
+            .map(|(r, d)| if *d > f64::EPSILON { r / d } else { *r })
+            .collect::<Vec<_>>()
+    };
+
+    let ax = operator(&x);
+    let mut r = b.iter().zip(&ax).map(|(b, ax)| b - ax).collect::<Vec<_>>();
+    let mut z = precondition(&r);
+    let mut p = z.clone();
+    let mut rz = dot(&r, &z);
+    let threshold = TOLERANCE * TOLERANCE * dot(b, b).max(f64::EPSILON);
+
+    for _ in 0..MAX_ITERATIONS {
+        if dot(&r, &r) <= threshold {
+            break;
+        }
+
+        let ap = operator(&p);
+        let step = rz / dot(&p, &ap).max(f64::EPSILON);
+        for i in 0..x.len() {
+            x[i] += step * p[i];
+            r[i] -= step * ap[i];
+        }
+
+        z = precondition(&r);
+        let rz_next = dot(&r, &z);
+        let beta = rz_next / rz;
+        for i in 0..p.len() {
+            p[i] = z[i] + beta * p[i];
+        }
+        rz = rz_next;
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const FOREGROUND: [f64; 3] = [220., 60., 40.];
+    const BACKGROUND: [f64; 3] = [30., 90., 200.];
+
+    /// Alpha of the synthetic edge: 0 left of x = 12, ramping to 1 at x = 20
+    fn true_alpha(x: u32) -> f64 {
+        ((x as f64 - 12.) / 8.).clamp(0., 1.)
+    }
+
+    /// Two flat colours blended across a fractional edge, with a trimap whose
+    /// unknown band covers the edge and the pixels next to it
+    fn synthetic_edge() -> (RgbaImage, GrayImage) {
+        let image = RgbaImage::from_fn(32, 12, |x, _| {
+            let alpha = true_alpha(x);
+            let blend =
+                |c: usize| (alpha * FOREGROUND[c] + (1. - alpha) * BACKGROUND[c]).round() as u8;
+            Rgba([blend(0), blend(1), blend(2), 255])
+        });
+        let trimap = GrayImage::from_fn(32, 12, |x, _| {
+            Luma([match x {
+                0..=9 => TRIMAP_BACKGROUND,
+                10..=22 => TRIMAP_UNKNOWN,
+                _ => TRIMAP_FOREGROUND,
+            }])
+        });
+        (image, trimap)
+    }
+
+    #[test]
+    fn recovers_fractional_edge() {
+        let (image, trimap) = synthetic_edge();
+        let initial = GrayImage::from_pixel(32, 12, Luma([128]));
+
+        let alpha = solve_closed_form(&image, &trimap, &initial);
+
+        for (x, y, p) in alpha.enumerate_pixels() {
+            let expected = true_alpha(x) * 255.;
+            assert!(
+                (p[0] as f64 - expected).abs() <= 3.,
+                "alpha at ({}, {}) is {}, expected {:.1}",
+                x,
+                y,
+                p[0],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn solution_stays_in_unit_range() {
+        let (image, trimap) = synthetic_edge();
+
+        // Start from both extremes so the solver has to travel across the range
+        for start in [0, 255] {
+            let initial = GrayImage::from_fn(32, 12, |x, y| match trimap[(x, y)][0] {
+                TRIMAP_UNKNOWN => Luma([start]),
+                value => Luma([value]),
+            });
+
+            let (positions, alpha) = solve_unknowns(&image, &trimap, &initial);
+
+            assert_eq!(positions.len(), 13 * 12);
+            for value in alpha {
+                assert!(
+                    (-1e-3..=1. + 1e-3).contains(&value),
+                    "alpha {} is outside [0, 1]",
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trimap_marks_band_around_edge() {
+        let mask = GrayImage::from_fn(40, 10, |x, _| Luma([if x < 20 { 0 } else { 255 }]));
+
+        let trimap = trimap(&mask, 3);
+
+        assert_eq!(trimap[(10, 5)][0], TRIMAP_BACKGROUND);
+        assert_eq!(trimap[(19, 5)][0], TRIMAP_UNKNOWN);
+        assert_eq!(trimap[(20, 5)][0], TRIMAP_UNKNOWN);
+        assert_eq!(trimap[(30, 5)][0], TRIMAP_FOREGROUND);
+    }
+}
@@ -0,0 +1,457 @@
+use anyhow::Result;
+use image::imageops;
+use image::{DynamicImage, GrayImage, Luma, RgbaImage};
+use ndarray::{Array2, Array3};
+
+use super::executor::BlockingExecutor;
+use super::model::Model;
+use crate::config::{
+    ChannelOrder, ModelProfile, OutputActivation, TensorLayout, DEFAULT_CROP_THRESHOLD,
+};
+
+mod alpha;
+mod background;
+mod batch;
+mod blur;
+mod canvas;
+mod color;
+mod components;
+mod foreground;
+mod matting;
+mod output;
+mod refine;
+
+pub use alpha::AlphaAdjust;
+pub use background::{Background, BackgroundFit, BackgroundImage, Fill, Gradient};
+pub use batch::{Batcher, InputSize};
+pub use blur::BackgroundBlur;
+pub use canvas::{parse_size, Align, CanvasLayout};
+pub use color::parse_color;
+pub use components::{Components, MaskCleanup};
+pub use foreground::decontaminate;
+pub use matting::{alpha_matting, MAX_BAND as MAX_MATTING_BAND};
+pub use output::{Encoding, MaskDepth, OutputFormat, OutputMode, PngCompression};
+
+use output::Matte;
+pub use refine::RefineMethod;
+
+pub struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub format: OutputFormat,
+}
+
+/// Per-request processing options
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    /// Crop the result to the bounds of the subject
+    pub crop: bool,
+    /// Alpha above which pixels count towards the crop bounds
+    pub crop_threshold: u8,
+    /// Ignore subject parts smaller than this many pixels when cropping
+    pub crop_min_area: u32,
+    /// Padding, aspect ratio and canvas the (cropped) subject is placed on
+    pub canvas: CanvasLayout,
+    /// Replace the transparent background, flattening the result
+    pub background: Option<Background>,
+    /// Blur the background instead of removing it
+    pub blur: Option<BackgroundBlur>,
+    /// Cut-out, mask or background output
+    pub output: OutputMode,
+    /// Bit depth of mask outputs
+    pub mask_depth: MaskDepth,
+    /// Output format and its quality settings
+    pub encoding: Encoding,
+    /// Pad the image to the model's input size instead of stretching it
+    pub letterbox: bool,
+    /// Downscale the output so its longest side is at most this many pixels
+    pub max_size: Option<u32>,
+    /// Edge refinement of the upsampled mask
+    pub refine: RefineMethod,
+    /// Guided filter window radius, defaults to 1/256 of the longest side
+    pub refine_radius: Option<u32>,
+    /// Guided filter regularization, defaults to `DEFAULT_REFINE_EPS`
+    pub refine_eps: Option<f32>,
+    /// Solve for fractional alpha around the subject's edge with trimap-based matting
+    pub matting: bool,
+    /// Width in pixels of the trimap's unknown band, defaults to 1/200 of the longest side
+    pub matting_band: Option<u32>,
+    /// Island removal and hole filling on the final mask
+    pub cleanup: MaskCleanup,
+    /// Thresholding, curves, morphology and feathering of the final alpha
+    pub alpha: AlphaAdjust,
+    /// Recover clean foreground colours at semi-transparent edges
+    pub decontaminate: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            crop: false,
+            crop_threshold: DEFAULT_CROP_THRESHOLD,
+            crop_min_area: 0,
+            canvas: CanvasLayout::default(),
+            background: None,
+            blur: None,
+            output: OutputMode::default(),
+            mask_depth: MaskDepth::default(),
+            encoding: Encoding::default(),
+            letterbox: false,
+            max_size: None,
+            refine: RefineMethod::default(),
+            refine_radius: None,
+            refine_eps: None,
+            matting: false,
+            matting_band: None,
+            cleanup: MaskCleanup::default(),
+            alpha: AlphaAdjust::default(),
+            decontaminate: false,
+        }
+    }
+}
+
+pub const DEFAULT_REFINE_EPS: f32 = 1e-3;
+
+// Function to find the bounding box containing non-transparent pixels
+fn find_alpha_bounds(
+    image: &RgbaImage,
+    threshold_bg: u8,
+    min_area: u32,
+) -> Option<(u32, u32, u32, u32)> {
+    let mut min_x = u32::MAX;
+    let mut max_x = 0;
+    let mut min_y = u32::MAX;
+    let mut max_y = 0;
+
+    // Stray specks shouldn't stretch the bounds
+    let components = (min_area > 0).then(|| {
+        let alpha = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            Luma([image[(x, y)][3]])
+        });
+        Components::label(&alpha, |a| a > threshold_bg, true)
+    });
+
+    for (i, (x, y, pixel)) in image.enumerate_pixels().enumerate() {
+        let too_small = components
+            .as_ref()
+            .is_some_and(|c| c.area_at(i) < min_area as usize);
+        if pixel[3] > threshold_bg && !too_small {
+            // Non-transparent pixel
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+    }
+
+    if min_x > max_x || min_y > max_y {
+        log::warn!(
+            "No non-transparent pixels found: {:?}",
+            (min_x, min_y, max_x, max_y)
+        );
+        return None;
+    }
+
+    Some((min_x, min_y, max_x, max_y))
+}
+
+pub async fn process_image(
+    model: &Model,
+    executor: &BlockingExecutor,
+    image_data: Vec<u8>,
+    options: ProcessOptions,
+) -> Result<ProcessedImage> {
+    let input_size = model.batcher.input_size();
+    let profile = model.profile.clone();
+    let letterbox = options.letterbox;
+    let format = options.encoding.format;
+
+    // Create image from bytes and build the model input
+    let (input_img, input_tensor, model_input) = executor
+        .run(move || -> Result<_> {
+            let input_img = image::load_from_memory(&image_data)?.into_rgba8();
+            let (input_tensor, model_input) =
+                build_input_tensor(&input_img, input_size, &profile, letterbox);
+            Ok((input_img, input_tensor, model_input))
+        })
+        .await??;
+
+    // Run the ONNX model, batched together with other pending images
+    let mut alpha = model.batcher.infer(input_tensor).await?;
+    let activation = model.profile.output_activation;
+
+    let data = executor
+        .run(move || -> Result<Vec<u8>> {
+            activate_output(&mut alpha, activation);
+
+            // Work at full resolution unless the caller asked for a smaller output
+            let (width, height) = output_size(input_img.dimensions(), options.max_size);
+            let output_img = if (width, height) == input_img.dimensions() {
+                input_img
+            } else {
+                imageops::resize(&input_img, width, height, imageops::FilterType::Triangle)
+            };
+
+            let matte = extract_matte(&alpha, &model_input, width, height);
+            let mut mask = quantize(&matte);
+
+            // 16-bit masks are built from the matte, so it's only kept for them
+            let wide_mask = (options.mask_depth == MaskDepth::Sixteen
+                && matches!(options.output, OutputMode::Mask | OutputMode::InverseMask))
+            .then(|| (matte, mask.clone()));
+
+            if options.refine == RefineMethod::Guided {
+                let radius = options
+                    .refine_radius
+                    .unwrap_or_else(|| refine::default_radius(width, height));
+                let eps = options.refine_eps.unwrap_or(DEFAULT_REFINE_EPS);
+                mask = refine::guided_filter(&output_img, &mask, radius, eps);
+            }
+
+            if options.matting {
+                let band = options
+                    .matting_band
+                    .unwrap_or_else(|| matting::default_band(width, height));
+                mask = alpha_matting(&output_img, &mask, band);
+            }
+
+            if options.cleanup.is_enabled() {
+                components::clean_mask(&mut mask, &options.cleanup);
+            }
+
+            if options.alpha.is_enabled() {
+                mask = alpha::adjust_alpha(mask, &options.alpha);
+            }
+
+            let processed = match (options.output, &options.blur) {
+                (OutputMode::Cutout, Some(blur)) => {
+                    DynamicImage::ImageRgba8(blur::blur_background(&output_img, &mask, blur))
+                }
+                (OutputMode::Cutout, None) => compose_cutout(output_img, &mask, &options),
+                (OutputMode::Mask | OutputMode::InverseMask, _) => {
+                    let invert = options.output == OutputMode::InverseMask;
+                    match &wide_mask {
+                        Some((matte, original)) => {
+                            output::wide_mask_image(matte, original, &mask, invert)
+                        }
+                        None => output::mask_image(mask, invert),
+                    }
+                }
+                (OutputMode::Background, _) => {
+                    let mut inverse = mask;
+                    inverse.pixels_mut().for_each(|p| p[0] = 255 - p[0]);
+                    compose_cutout(output_img, &inverse, &options)
+                }
+            };
+
+            // Convert back to bytes
+            output::encode(processed, &options.encoding)
+        })
+        .await??;
+
+    Ok(ProcessedImage { data, format })
+}
+
+/// Cuts the subject out with `mask` and lays it out as requested
+fn compose_cutout(image: RgbaImage, mask: &GrayImage, options: &ProcessOptions) -> DynamicImage {
+    let mut image = apply_mask(image, mask);
+
+    if options.decontaminate {
+        decontaminate(&mut image);
+    }
+
+    let mut processed = if options.crop {
+        crop_to_alpha_bounds(image, options.crop_threshold, options.crop_min_area)
+    } else {
+        DynamicImage::ImageRgba8(image)
+    };
+
+    if options.canvas.is_enabled() {
+        processed = DynamicImage::ImageRgba8(canvas::place_on_canvas(
+            processed.into_rgba8(),
+            &options.canvas,
+        ));
+    }
+
+    if let Some(background) = &options.background {
+        processed =
+            DynamicImage::ImageRgba8(background::composite(&processed.into_rgba8(), background));
+    }
+
+    processed
+}
+
+fn crop_to_alpha_bounds(mut image: RgbaImage, threshold: u8, min_area: u32) -> DynamicImage {
+    match find_alpha_bounds(&image, threshold, min_area) {
+        Some((min_x, min_y, max_x, max_y)) => DynamicImage::ImageRgba8(
+            imageops::crop(
+                &mut image,
+                min_x,
+                min_y,
+                max_x - min_x + 1,
+                max_y - min_y + 1,
+            )
+            .to_image(),
+        ),
+        // If no valid bounds found, keep the processed image without cropping
+        None => DynamicImage::ImageRgba8(image),
+    }
+}
+
+/// Where an image was placed in the model input
+struct ModelInput {
+    /// Spatial size of the tensor as (width, height)
+    size: (u32, u32),
+    /// Size of the area of the tensor covered by the image
+    content_size: (u32, u32),
+    /// Position of the image in the tensor, non-zero when letterboxed
+    offset: (u32, u32),
+}
+
+fn build_input_tensor(
+    input_img: &RgbaImage,
+    input_size: InputSize,
+    profile: &ModelProfile,
+    letterbox: bool,
+) -> (Array3<f32>, ModelInput) {
+    let (height, width) = input_size.for_image(input_img.width(), input_img.height());
+
+    // Letterboxing keeps the aspect ratio and pads the rest of the tensor
+    let (resized_width, resized_height) = if letterbox {
+        let scale = f32::min(
+            width as f32 / input_img.width() as f32,
+            height as f32 / input_img.height() as f32,
+        );
+        (
+            ((input_img.width() as f32 * scale).round() as usize).clamp(1, width),
+            ((input_img.height() as f32 * scale).round() as usize).clamp(1, height),
+        )
+    } else {
+        (width, height)
+    };
+    let (offset_x, offset_y) = ((width - resized_width) / 2, (height - resized_height) / 2);
+
+    let resized_img = imageops::resize(
+        input_img,
+        resized_width as u32,
+        resized_height as u32,
+        imageops::FilterType::Triangle,
+    );
+
+    let normalize = |channel: usize, y: usize, x: usize| {
+        let (Some(x), Some(y)) = (x.checked_sub(offset_x), y.checked_sub(offset_y)) else {
+            return 0.; // Padding is the normalized mean
+        };
+        if x >= resized_width || y >= resized_height {
+            return 0.;
+        }
+
+        let source_channel = match profile.channel_order {
+            ChannelOrder::Rgb => channel,
+            ChannelOrder::Bgr => 2 - channel,
+        };
+        let value = resized_img[(x as u32, y as u32)][source_channel] as f32 / 255.;
+        (value - profile.mean[channel]) / profile.std[channel]
+    };
+
+    let tensor = match profile.layout {
+        TensorLayout::Nchw => {
+            Array3::from_shape_fn((3, height, width), |(c, y, x)| normalize(c, y, x))
+        }
+        TensorLayout::Nhwc => {
+            Array3::from_shape_fn((height, width, 3), |(y, x, c)| normalize(c, y, x))
+        }
+    };
+
+    let model_input = ModelInput {
+        size: (width as u32, height as u32),
+        content_size: (resized_width as u32, resized_height as u32),
+        offset: (offset_x as u32, offset_y as u32),
+    };
+
+    (tensor, model_input)
+}
+
+/// Maps raw model output to alpha values in `[0, 1]`
+fn activate_output(output: &mut Array2<f32>, activation: OutputActivation) {
+    match activation {
+        OutputActivation::None => {}
+        OutputActivation::Sigmoid => output.mapv_inplace(|v| 1. / (1. + (-v).exp())),
+        OutputActivation::MinMax => {
+            let min = output.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = output.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let range = (max - min).max(f32::EPSILON);
+            output.mapv_inplace(|v| (v - min) / range);
+        }
+    }
+}
+
+/// Upper bound on the pixels of the image produced from an input of the given
+/// size, so oversized canvases can be rejected before any work is queued
+pub fn output_pixels(dimensions: (u32, u32), options: &ProcessOptions) -> u64 {
+    let (width, height) = output_size(dimensions, options.max_size);
+    let has_canvas = options.canvas.is_enabled()
+        && options.blur.is_none()
+        && matches!(options.output, OutputMode::Cutout | OutputMode::Background);
+
+    if has_canvas {
+        options.canvas.pixels(width, height)
+    } else {
+        width as u64 * height as u64
+    }
+}
+
+/// Size of the output image, keeping the input size unless it exceeds `max_size`
+fn output_size((width, height): (u32, u32), max_size: Option<u32>) -> (u32, u32) {
+    match max_size {
+        Some(max_size) if width.max(height) > max_size => {
+            let scaling_factor = max_size as f32 / width.max(height) as f32;
+            (
+                ((width as f32 * scaling_factor) as u32).max(1),
+                ((height as f32 * scaling_factor) as u32).max(1),
+            )
+        }
+        _ => (width, height),
+    }
+}
+
+/// Turns the model's alpha prediction into a `width x height` matte
+fn extract_matte(alpha: &Array2<f32>, model_input: &ModelInput, width: u32, height: u32) -> Matte {
+    let (input_width, input_height) = model_input.size;
+    let (content_width, content_height) = model_input.content_size;
+    let (offset_x, offset_y) = model_input.offset;
+
+    let (mask_height, mask_width) = alpha.dim();
+    let mut mask = Matte::from_fn(mask_width as u32, mask_height as u32, |x, y| {
+        Luma([alpha[(y as usize, x as usize)].clamp(0., 1.)])
+    });
+
+    // Some models predict the mask at a different resolution than their input
+    if mask.dimensions() != (input_width, input_height) {
+        mask = imageops::resize(
+            &mask,
+            input_width,
+            input_height,
+            imageops::FilterType::Triangle,
+        );
+    }
+
+    // Drop the letterbox padding so the mask lines up with the image
+    let mask = imageops::crop_imm(&mask, offset_x, offset_y, content_width, content_height);
+
+    imageops::resize(&*mask, width, height, imageops::FilterType::Triangle)
+}
+
+/// Quantizes `matte` to the 8-bit mask the rest of the pipeline works on
+fn quantize(matte: &Matte) -> GrayImage {
+    GrayImage::from_fn(matte.width(), matte.height(), |x, y| {
+        Luma([(matte[(x, y)][0] * 255.).round() as u8])
+    })
+}
+
+/// Replaces the alpha channel of `image` with `mask`
+fn apply_mask(mut image: RgbaImage, mask: &GrayImage) -> RgbaImage {
+    for (pixel, alpha) in image.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = alpha[0];
+    }
+    image
+}
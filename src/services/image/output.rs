@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Result};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{imageops, DynamicImage, GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use serde::Deserialize;
+
+/// What the processed image contains
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    /// The subject on a transparent (or replaced) background
+    #[default]
+    Cutout,
+    /// The alpha matte as a grayscale image
+    Mask,
+    /// The inverted alpha matte, white where the background is
+    InverseMask,
+    /// The background with the subject cut out
+    Background,
+}
+
+/// Bit depth of grayscale mask outputs
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MaskDepth {
+    #[default]
+    Eight,
+    Sixteen,
+}
+
+/// The model's alpha at full precision, in `[0, 1]`
+pub type Matte = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// Turns `mask` into an 8-bit grayscale image, inverted if requested
+pub fn mask_image(mask: GrayImage, invert: bool) -> DynamicImage {
+    let mut mask = mask;
+    if invert {
+        mask.pixels_mut().for_each(|p| p[0] = 255 - p[0]);
+    }
+    DynamicImage::ImageLuma8(mask)
+}
+
+/// Turns `matte` into a 16-bit grayscale image, inverted if requested.
+/// `edited` is the 8-bit mask after refinement and cleanup of `original`, the
+/// quantized matte. Pixels it changed take its value, so binarized or filled
+/// pixels stay exactly 0 or 65535, and the rest keep the model's precision.
+pub fn wide_mask_image(
+    matte: &Matte,
+    original: &GrayImage,
+    edited: &GrayImage,
+    invert: bool,
+) -> DynamicImage {
+    let (width, height) = matte.dimensions();
+    DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+        let edited = edited[(x, y)][0];
+        let alpha = if edited != original[(x, y)][0] {
+            edited as u16 * 257
+        } else {
+            (matte[(x, y)][0].clamp(0., 1.) * 65535.).round() as u16
+        };
+        Luma([if invert { u16::MAX - alpha } else { alpha }])
+    }))
+}
+
+/// Encoding of the processed image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    /// Lossless or lossy WebP with alpha
+    Webp,
+    /// AVIF with alpha
+    Avif,
+    /// JPEG, flattened onto a colour since it has no alpha
+    Jpeg,
+}
+
+impl OutputFormat {
+    /// File extension uploads are stored with
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+            OutputFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub fn supports_alpha(&self) -> bool {
+        *self != OutputFormat::Jpeg
+    }
+}
+
+/// PNG compression effort
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+pub const DEFAULT_WEBP_QUALITY: u8 = 80;
+pub const DEFAULT_AVIF_QUALITY: u8 = 80;
+/// AVIF encoder speed from 1 (slowest, smallest) to 10 (fastest)
+const AVIF_SPEED: u8 = 6;
+
+/// How the processed image is encoded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Encoding {
+    pub format: OutputFormat,
+    /// Quality from 1 to 100 for JPEG, AVIF and lossy WebP
+    pub quality: Option<u8>,
+    /// Encode WebP losslessly
+    pub lossless: bool,
+    pub png_compression: PngCompression,
+    /// Colour JPEG output is flattened onto, white by default
+    pub flatten_color: Option<Rgba<u8>>,
+}
+
+/// Encodes `image` as requested by `encoding`
+pub fn encode(image: DynamicImage, encoding: &Encoding) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+
+    match encoding.format {
+        OutputFormat::Png => {
+            let compression = match encoding.png_compression {
+                PngCompression::Fast => CompressionType::Fast,
+                PngCompression::Default => CompressionType::Default,
+                PngCompression::Best => CompressionType::Best,
+            };
+            let encoder =
+                PngEncoder::new_with_quality(&mut buffer, compression, FilterType::Adaptive);
+            image.write_with_encoder(encoder)?;
+        }
+        OutputFormat::Webp if !encoding.lossless => {
+            buffer = encode_lossy_webp(&image, encoding.quality.unwrap_or(DEFAULT_WEBP_QUALITY))?;
+        }
+        OutputFormat::Webp => {
+            let image = match image {
+                DynamicImage::ImageLuma16(_) => DynamicImage::ImageLuma8(image.to_luma8()),
+                image => image,
+            };
+            image.write_with_encoder(WebPEncoder::new_lossless(&mut buffer))?;
+        }
+        OutputFormat::Avif => {
+            let image = match image {
+                DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => image,
+                image => DynamicImage::ImageRgba8(image.to_rgba8()),
+            };
+            let quality = encoding.quality.unwrap_or(DEFAULT_AVIF_QUALITY);
+            let encoder = AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_SPEED, quality);
+            image.write_with_encoder(encoder)?;
+        }
+        OutputFormat::Jpeg => {
+            let image = match image {
+                DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => image,
+                DynamicImage::ImageLuma16(_) => DynamicImage::ImageLuma8(image.to_luma8()),
+                image => {
+                    let color = encoding.flatten_color.unwrap_or(Rgba([255, 255, 255, 255]));
+                    DynamicImage::ImageRgb8(flatten(&image.to_rgba8(), color))
+                }
+            };
+            let quality = encoding.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            image.write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, quality))?;
+        }
+    }
+
+    Ok(buffer)
+}
+
+/// Lossy WebP through libwebp, keeping the alpha channel when there is one
+fn encode_lossy_webp(image: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let (width, height) = (image.width(), image.height());
+    let encoded = if image.color().has_alpha() {
+        let rgba = image.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, width, height).encode_simple(false, quality as f32)
+    } else {
+        let rgb = image.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, width, height).encode_simple(false, quality as f32)
+    };
+
+    encoded
+        .map(|memory| memory.to_vec())
+        .map_err(|e| anyhow!("WebP encoding failed: {:?}", e))
+}
+
+/// Composites `image` onto an opaque `color`, dropping the alpha channel
+fn flatten(image: &RgbaImage, color: Rgba<u8>) -> image::RgbImage {
+    let mut canvas = RgbaImage::from_pixel(
+        image.width(),
+        image.height(),
+        Rgba([color[0], color[1], color[2], 255]),
+    );
+    imageops::overlay(&mut canvas, image, 0, 0);
+    DynamicImage::ImageRgba8(canvas).to_rgb8()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webp(lossless: bool) -> Vec<u8> {
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 0, 128]));
+        let encoding = Encoding {
+            format: OutputFormat::Webp,
+            lossless,
+            ..Default::default()
+        };
+        encode(DynamicImage::ImageRgba8(image), &encoding).unwrap()
+    }
+
+    #[test]
+    fn wide_mask_keeps_matte_precision() {
+        let values = [0.3001, 0.7, 0.9, 0.08];
+        let matte = Matte::from_fn(4, 1, |x, _| Luma([values[x as usize]]));
+        let original = GrayImage::from_fn(4, 1, |x, _| Luma([[77, 179, 230, 20][x as usize]]));
+        // The second pixel was raised by cleanup and the last two were binarized
+        let edited = GrayImage::from_fn(4, 1, |x, _| Luma([[77, 180, 255, 0][x as usize]]));
+
+        let DynamicImage::ImageLuma16(mask) = wide_mask_image(&matte, &original, &edited, false)
+        else {
+            panic!("expected a 16-bit mask");
+        };
+        assert_eq!(mask[(0, 0)][0], (0.3001f32 * 65535.).round() as u16);
+        assert_eq!(mask[(1, 0)][0], 180 * 257);
+        assert_eq!(mask[(2, 0)][0], 65535);
+        assert_eq!(mask[(3, 0)][0], 0);
+
+        let DynamicImage::ImageLuma16(inverse) =
+            wide_mask_image(&matte, &original, &original, true)
+        else {
+            panic!("expected a 16-bit mask");
+        };
+        assert_eq!(inverse[(1, 0)][0], 65535 - (0.7f32 * 65535.).round() as u16);
+    }
+
+    #[test]
+    fn encodes_lossless_and_lossy_webp() {
+        let lossless = webp(true);
+        let lossy = webp(false);
+
+        assert_eq!(&lossless[..4], b"RIFF");
+        assert!(lossless.windows(4).any(|chunk| chunk == b"VP8L"));
+        assert_eq!(&lossy[..4], b"RIFF");
+        assert!(lossy.windows(4).any(|chunk| chunk == b"VP8 "));
+        // Lossy WebP stores alpha in its own chunk
+        assert!(lossy.windows(4).any(|chunk| chunk == b"ALPH"));
+    }
+}
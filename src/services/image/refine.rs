@@ -0,0 +1,88 @@
+use image::{GrayImage, Luma, RgbaImage};
+use serde::Deserialize;
+
+/// Edge refinement applied to the upsampled mask
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefineMethod {
+    #[default]
+    None,
+    /// Guided filter using the full-resolution image as guidance
+    Guided,
+}
+
+/// Default guided filter radius for an image, about 1/256 of its longest side
+pub fn default_radius(width: u32, height: u32) -> u32 {
+    (width.max(height) / 256).max(2)
+}
+
+/// Edge-preserving guided filter (He et al.) of `mask`, guided by the
+/// luminance of `guide`. Larger `radius` snaps the mask to image edges over
+/// a wider band; smaller `eps` follows the edges more closely.
+pub fn guided_filter(guide: &RgbaImage, mask: &GrayImage, radius: u32, eps: f32) -> GrayImage {
+    let (width, height) = mask.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    let luminance = guide
+        .pixels()
+        .map(|p| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.)
+        .collect::<Vec<_>>();
+    let alpha = mask
+        .pixels()
+        .map(|p| p[0] as f32 / 255.)
+        .collect::<Vec<_>>();
+
+    let product = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(x, y)| x * y).collect::<Vec<_>>();
+
+    let mean_i = box_filter(&luminance, w, h, radius as usize);
+    let mean_p = box_filter(&alpha, w, h, radius as usize);
+    let corr_ii = box_filter(&product(&luminance, &luminance), w, h, radius as usize);
+    let corr_ip = box_filter(&product(&luminance, &alpha), w, h, radius as usize);
+
+    let mut a = vec![0.; w * h];
+    let mut b = vec![0.; w * h];
+    for i in 0..w * h {
+        let var_i = corr_ii[i] - mean_i[i] * mean_i[i];
+        let cov_ip = corr_ip[i] - mean_i[i] * mean_p[i];
+        a[i] = cov_ip / (var_i + eps);
+        b[i] = mean_p[i] - a[i] * mean_i[i];
+    }
+
+    let mean_a = box_filter(&a, w, h, radius as usize);
+    let mean_b = box_filter(&b, w, h, radius as usize);
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let i = y as usize * w + x as usize;
+        let q = mean_a[i] * luminance[i] + mean_b[i];
+        Luma([(q.clamp(0., 1.) * 255.).round() as u8])
+    })
+}
+
+/// Mean over a `(2 * radius + 1)` square window, clipped at the image borders
+pub(super) fn box_filter(values: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    // Summed-area table with an extra zero row and column
+    let stride = width + 1;
+    let mut integral = vec![0f64; stride * (height + 1)];
+    for y in 0..height {
+        let mut row_sum = 0f64;
+        for x in 0..width {
+            row_sum += values[y * width + x] as f64;
+            integral[(y + 1) * stride + x + 1] = integral[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    let mut output = vec![0.; width * height];
+    for y in 0..height {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let sum = integral[y1 * stride + x1]
+                - integral[y0 * stride + x1]
+                - integral[y1 * stride + x0]
+                + integral[y0 * stride + x0];
+            output[y * width + x] = (sum / ((y1 - y0) * (x1 - x0)) as f64) as f32;
+        }
+    }
+
+    output
+}
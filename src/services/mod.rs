@@ -1,4 +1,5 @@
+pub mod executor;
 pub mod image;
+pub mod model;
 pub mod onnx;
 pub mod upload;
-
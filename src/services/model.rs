@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::executor::BlockingExecutor;
+use super::image::Batcher;
+use super::onnx::SessionPool;
+use crate::config::{InferenceConfig, ModelConfig, ModelProfile, ModelSource};
+
+/// A loaded model with its own session pool and batching scheduler
+pub struct Model {
+    pub name: String,
+    pub path: String,
+    pub profile: ModelProfile,
+    pub batcher: Batcher,
+    session_pool: Arc<SessionPool>,
+}
+
+impl Model {
+    pub fn load(
+        source: &ModelSource,
+        executor: Arc<BlockingExecutor>,
+        config: &InferenceConfig,
+    ) -> Result<Self> {
+        let pool_size = source.pool_size.unwrap_or(config.pool_size);
+        let session_pool = Arc::new(SessionPool::new(&source.path, pool_size, config)?);
+
+        // Parameters embedded in the model file take precedence over the configured profile
+        let metadata = session_pool.metadata()?;
+        let profile = source
+            .profile
+            .clone()
+            .with_metadata(|key| Ok(metadata.custom(key)?))?;
+
+        let batcher = Batcher::new(Arc::clone(&session_pool), executor, config, &profile)?;
+
+        Ok(Self {
+            name: source.name.clone(),
+            path: source.path.clone(),
+            profile,
+            batcher,
+            session_pool,
+        })
+    }
+
+    pub fn pool_size(&self) -> usize {
+        self.session_pool.size()
+    }
+}
+
+/// All models loaded at startup, looked up by name for each request
+pub struct ModelRegistry {
+    models: HashMap<String, Arc<Model>>,
+    default: String,
+}
+
+impl ModelRegistry {
+    pub fn load(
+        model_config: &ModelConfig,
+        executor: Arc<BlockingExecutor>,
+        config: &InferenceConfig,
+    ) -> Result<Self> {
+        let mut models = HashMap::new();
+
+        for source in &model_config.sources {
+            let model = Model::load(source, Arc::clone(&executor), config).with_context(|| {
+                format!(
+                    "Failed to load model '{}' from {}",
+                    source.name, source.path
+                )
+            })?;
+            models.insert(source.name.clone(), Arc::new(model));
+        }
+
+        Ok(Self {
+            models,
+            default: model_config.default.clone(),
+        })
+    }
+
+    /// Returns the named model, or the default one when no name is given
+    pub fn get(&self, name: Option<&str>) -> Option<&Arc<Model>> {
+        let name = name.map_or_else(|| self.default.clone(), str::to_lowercase);
+        self.models.get(&name)
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    /// Names of all loaded models, sorted for stable error messages and logs
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.models.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = &Arc<Model>> {
+        self.models.values()
+    }
+}
@@ -1,10 +1,23 @@
-use ort::{Environment, ExecutionProvider, GraphOptimizationLevel, SessionBuilder};
+use anyhow::{anyhow, Result};
+use ort::metadata::Metadata;
+use ort::{Environment, ExecutionProvider, GraphOptimizationLevel, Session, SessionBuilder};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
-pub(crate) fn onnx_session(onnx_model_file: &str) -> Result<ort::Session, anyhow::Error> {
-    let environment = Environment::default().into_arc();
-    let session = SessionBuilder::new(&environment)?
+use crate::config::InferenceConfig;
+
+pub(crate) fn onnx_session(
+    environment: &Arc<Environment>,
+    onnx_model_file: &str,
+    config: &InferenceConfig,
+) -> Result<Session> {
+    let session = SessionBuilder::new(environment)?
         .with_optimization_level(GraphOptimizationLevel::Level1)? // Configure model optimization level
-        .with_intra_threads(1)? // Configure the number of threads used for inference
+        .with_intra_threads(config.intra_threads)? // Configure the number of threads used for inference
+        .with_inter_threads(config.inter_threads)?
         .with_execution_providers([
             // Configure execution providers (e.g., CUDA, CoreML, CPU)
             ExecutionProvider::CUDA(Default::default()),
@@ -14,3 +27,106 @@ pub(crate) fn onnx_session(onnx_model_file: &str) -> Result<ort::Session, anyhow
         .with_model_from_file(onnx_model_file)?;
     Ok(session)
 }
+
+#[derive(Error, Debug, Clone)]
+pub enum PoolError {
+    #[error("Inference queue is full ({0} requests waiting)")]
+    QueueFull(usize),
+
+    #[error("Session pool is closed")]
+    Closed,
+}
+
+/// A fixed set of ONNX sessions for one model. Admission is bounded by a
+/// semaphore with one permit per session, and at most `queue_depth` callers
+/// may wait for a permit at any time.
+pub struct SessionPool {
+    sessions: Vec<Session>,
+    idle: Mutex<Vec<usize>>,
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    queue_depth: usize,
+}
+
+impl SessionPool {
+    pub fn new(onnx_model_file: &str, size: usize, config: &InferenceConfig) -> Result<Self> {
+        if size == 0 {
+            return Err(anyhow!("Session pool size must be at least 1"));
+        }
+
+        let environment = Environment::default().into_arc();
+        let sessions = (0..size)
+            .map(|_| onnx_session(&environment, onnx_model_file, config))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            idle: Mutex::new((0..sessions.len()).collect()),
+            semaphore: Arc::new(Semaphore::new(sessions.len())),
+            sessions,
+            waiting: AtomicUsize::new(0),
+            queue_depth: config.queue_depth,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Metadata embedded in the model file
+    pub fn metadata(&self) -> Result<Metadata> {
+        Ok(self.sessions[0].metadata()?)
+    }
+
+    /// Declared shape of the model's first input, `None` for dynamic dimensions
+    pub fn input_dimensions(&self) -> Vec<Option<usize>> {
+        self.sessions[0].inputs[0].dimensions().collect()
+    }
+
+    /// Waits for a free session, or fails immediately if too many callers are already waiting
+    pub async fn acquire(self: &Arc<Self>) -> Result<PooledSession, PoolError> {
+        let waiting = self.waiting.fetch_add(1, Ordering::SeqCst);
+        if waiting >= self.queue_depth + self.sessions.len() {
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(PoolError::QueueFull(waiting));
+        }
+
+        let permit = Arc::clone(&self.semaphore).acquire_owned().await;
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        let permit = permit.map_err(|_| PoolError::Closed)?;
+
+        let index = self
+            .idle
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("semaphore permit granted without an idle session");
+
+        Ok(PooledSession {
+            pool: Arc::clone(self),
+            index,
+            _permit: permit,
+        })
+    }
+}
+
+/// A session checked out of a [`SessionPool`], returned to it on drop
+pub struct PooledSession {
+    pool: Arc<SessionPool>,
+    index: usize,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.pool.sessions[self.index]
+    }
+}
+
+impl Drop for PooledSession {
+    fn drop(&mut self) {
+        // Return the index before the permit is released so the next holder finds it
+        self.pool.idle.lock().unwrap().push(self.index);
+    }
+}
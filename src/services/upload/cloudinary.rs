@@ -1,4 +1,4 @@
-use super::ImageUploader;
+use super::{content_type, ImageUploader, UploadedFile};
 use async_trait::async_trait;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use cloudinary::upload::{OptionalParameters, Source, Upload, result::UploadResult};
@@ -26,10 +26,10 @@ impl CloudinaryUploader {
 
 #[async_trait]
 impl ImageUploader for CloudinaryUploader {
-    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<String, Error> {
+    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<UploadedFile, Error> {
         let unique_filename = format!("{}", Uuid::new_v4());
         let base64_data = BASE64.encode(image_data);
-        let data_url = format!("data:image/{};base64,{}", format, base64_data);
+        let data_url = format!("data:{};base64,{}", content_type(format), base64_data);
 
         let options = BTreeSet::from([
             OptionalParameters::PublicId(unique_filename),
@@ -43,8 +43,14 @@ impl ImageUploader for CloudinaryUploader {
             .map_err(|e| anyhow::anyhow!("Cloudinary upload failed: {}", e))?;
 
         match response {
-            UploadResult::Response(response) => Ok(response.secure_url),
-            UploadResult::ResponseWithImageMetadata(response) => Ok(response.secure_url),
+            UploadResult::Response(response) => Ok(UploadedFile {
+                url: response.secure_url,
+                id: response.public_id,
+            }),
+            UploadResult::ResponseWithImageMetadata(response) => Ok(UploadedFile {
+                url: response.secure_url,
+                id: response.public_id,
+            }),
             UploadResult::Error(error) => {
                 log::error!("Upload failed: {:?}", error);
                 Err(anyhow::anyhow!("Upload failed"))
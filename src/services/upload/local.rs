@@ -0,0 +1,74 @@
+use super::{ImageUploader, UploadedFile};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+/// Stores images on the local filesystem, served back by the files route
+#[derive(Clone)]
+pub struct LocalUploader {
+    directory: PathBuf,
+    public_base_url: String,
+}
+
+impl LocalUploader {
+    pub async fn new(directory: &str, public_base_url: &str) -> Result<Self> {
+        tokio::fs::create_dir_all(directory)
+            .await
+            .map_err(|e| anyhow!("Failed to create storage directory {}: {}", directory, e))?;
+
+        Ok(Self {
+            directory: PathBuf::from(directory),
+            public_base_url: public_base_url.trim_end_matches('/').to_owned(),
+        })
+    }
+}
+
+/// Resolves a relative storage key inside `root`, rejecting keys that could
+/// escape it such as absolute paths or `..` components
+fn resolve_path(root: &Path, key: &str) -> Option<PathBuf> {
+    let key = Path::new(key);
+    let is_safe = key
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    (is_safe && key.components().next().is_some()).then(|| root.join(key))
+}
+
+#[async_trait]
+impl ImageUploader for LocalUploader {
+    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<UploadedFile> {
+        // Shard by date so no single directory grows without bound
+        let date = chrono::Utc::now().format("%Y/%m/%d");
+        let key = match folder.trim_matches('/') {
+            "" => format!("{}/{}.{}", date, Uuid::new_v4(), format),
+            folder => format!("{}/{}/{}.{}", folder, date, Uuid::new_v4(), format),
+        };
+        let path = resolve_path(&self.directory, &key)
+            .ok_or_else(|| anyhow!("Invalid storage folder '{}'", folder))?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, image_data)
+            .await
+            .map_err(|e| anyhow!("Local upload failed: {}", e))?;
+
+        Ok(UploadedFile {
+            url: format!("{}/{}", self.public_base_url, key),
+            id: key,
+        })
+    }
+
+    async fn delete(&self, file_id: &str) -> Result<bool> {
+        let path = resolve_path(&self.directory, file_id)
+            .ok_or_else(|| anyhow!("Invalid file id '{}'", file_id))?;
+
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(anyhow!("Failed to delete local file: {}", e)),
+        }
+    }
+}
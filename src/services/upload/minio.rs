@@ -1,11 +1,12 @@
-use super::ImageUploader;
+use super::{content_type, ImageUploader, UploadedFile};
 use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
-use minio_rsc::client::BucketArgs;
+use minio_rsc::client::{BucketArgs, KeyArgs};
 use minio_rsc::provider::StaticProvider;
 use minio_rsc::Minio;
 use std::sync::Arc;
+use tokio::sync::OnceCell;
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -13,10 +14,12 @@ pub struct MinioUploader {
     client: Arc<Minio>,
     bucket: String,
     endpoint: String,
+    /// Set once the bucket is known to exist, checked on the first upload
+    bucket_ready: Arc<OnceCell<()>>,
 }
 
 impl MinioUploader {
-    pub async fn new(
+    pub fn new(
         access_key: &str,
         secret_key: &str,
         bucket: &str,
@@ -35,14 +38,6 @@ impl MinioUploader {
             .secure(secure)
             .build()?;
 
-        // Check if bucket exists
-        let (buckets, _) = client.list_buckets().await?;
-        let bucket_exists = buckets.iter().any(|b| b.name == bucket);
-
-        if !bucket_exists {
-            client.make_bucket(BucketArgs::new(bucket), false).await?;
-        }
-
         Ok(Self {
             client: Arc::new(client),
             bucket: bucket.to_owned(),
@@ -51,9 +46,30 @@ impl MinioUploader {
             } else {
                 format!("{}/", endpoint)
             },
+            bucket_ready: Arc::new(OnceCell::new()),
         })
     }
 
+    /// Creates the bucket if it doesn't exist yet, so startup doesn't need
+    /// MinIO to be reachable
+    async fn ensure_bucket(&self) -> Result<()> {
+        self.bucket_ready
+            .get_or_try_init(|| async {
+                let (buckets, _) = self.client.list_buckets().await?;
+                let bucket_exists = buckets.iter().any(|b| b.name == self.bucket);
+
+                if !bucket_exists {
+                    self.client
+                        .make_bucket(BucketArgs::new(&self.bucket), false)
+                        .await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     fn build_url(&self, key: &str) -> String {
         format!("{}{}/{}", self.endpoint, self.bucket, key)
     }
@@ -61,15 +77,24 @@ impl MinioUploader {
 
 #[async_trait]
 impl ImageUploader for MinioUploader {
-    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<String> {
+    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<UploadedFile> {
+        self.ensure_bucket().await?;
+
         let key = format!("{}/{}.{}", folder, Uuid::new_v4(), format);
 
         self.client
-            .put_object(&self.bucket, &key, Bytes::copy_from_slice(image_data))
+            .put_object(
+                &self.bucket,
+                KeyArgs::new(&key).content_type(Some(content_type(format))),
+                Bytes::copy_from_slice(image_data),
+            )
             .await
             .map_err(|e| anyhow::anyhow!("MinIO upload failed: {}", e))?;
 
-        Ok(self.build_url(&key))
+        Ok(UploadedFile {
+            url: self.build_url(&key),
+            id: key,
+        })
     }
 
     async fn delete(&self, file_id: &str) -> Result<bool> {
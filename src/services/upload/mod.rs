@@ -1,53 +1,156 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 
 pub(crate) mod cloudinary;
+pub(crate) mod local;
 pub(crate) mod minio;
 pub(crate) mod s3;
 
 pub use cloudinary::CloudinaryUploader;
+pub use local::LocalUploader;
 pub use minio::MinioUploader;
 pub use s3::S3Uploader;
 
-#[derive(Debug, Clone, Deserialize, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Deserialize, Hash, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum UploaderType {
     Cloudinary,
     S3,
     Minio,
+    /// Files on the local filesystem, served by the server itself
+    Local,
     // Add more uploader types here
 }
 
-impl Default for UploaderType {
-    fn default() -> Self {
-        Self::Cloudinary
+impl UploaderType {
+    /// Every uploader type, in the order the default uploader is picked from
+    pub const ALL: [UploaderType; 4] = [
+        UploaderType::Cloudinary,
+        UploaderType::S3,
+        UploaderType::Minio,
+        UploaderType::Local,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UploaderType::Cloudinary => "cloudinary",
+            UploaderType::S3 => "s3",
+            UploaderType::Minio => "minio",
+            UploaderType::Local => "local",
+        }
+    }
+
+    /// Environment variables that enable this uploader when set
+    pub fn required_env(&self) -> &'static str {
+        match self {
+            UploaderType::Cloudinary => {
+                "CLOUDINARY_CLOUD_NAME, CLOUDINARY_API_KEY, CLOUDINARY_API_SECRET"
+            }
+            UploaderType::S3 => "AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, S3_BUCKET",
+            UploaderType::Minio => {
+                "MINIO_ACCESS_KEY, MINIO_SECRET_KEY, MINIO_BUCKET, MINIO_ENDPOINT"
+            }
+            UploaderType::Local => "LOCAL_STORAGE_DIR",
+        }
+    }
+}
+
+impl FromStr for UploaderType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_lowercase();
+        UploaderType::ALL
+            .into_iter()
+            .find(|uploader_type| uploader_type.name() == name)
+            .ok_or_else(|| anyhow!("Unknown uploader '{}'", s))
     }
 }
 
-#[allow(dead_code)]
+/// A stored image, with the id needed to delete it again
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    pub url: String,
+    pub id: String,
+}
+
 #[async_trait]
 pub trait ImageUploader: Send + Sync + 'static {
-    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<String>;
+    async fn upload(&self, image_data: &[u8], format: &str, folder: &str) -> Result<UploadedFile>;
     async fn delete(&self, file_id: &str) -> Result<bool>;
 }
 
 pub type DynImageUploader = Arc<dyn ImageUploader>;
 
+/// MIME type of an image stored with the given file extension
+pub fn content_type(extension: &str) -> String {
+    match extension {
+        "jpg" | "jpeg" => "image/jpeg".to_string(),
+        extension => format!("image/{}", extension),
+    }
+}
+
+/// Uploaders enabled at startup, looked up by type for each request
+pub struct UploaderRegistry {
+    uploaders: HashMap<UploaderType, DynImageUploader>,
+    default: Option<UploaderType>,
+}
+
+impl UploaderRegistry {
+    pub fn new(
+        uploaders: HashMap<UploaderType, DynImageUploader>,
+        default: Option<UploaderType>,
+    ) -> Self {
+        Self { uploaders, default }
+    }
+
+    /// Returns the requested uploader, or the default one when none is given
+    pub fn get(
+        &self,
+        uploader_type: Option<UploaderType>,
+    ) -> Option<(UploaderType, &DynImageUploader)> {
+        let uploader_type = uploader_type.or(self.default)?;
+        self.uploaders
+            .get(&uploader_type)
+            .map(|uploader| (uploader_type, uploader))
+    }
+
+    pub fn default_type(&self) -> Option<UploaderType> {
+        self.default
+    }
+
+    pub fn is_enabled(&self, uploader_type: UploaderType) -> bool {
+        self.uploaders.contains_key(&uploader_type)
+    }
+
+    /// Names of all enabled uploaders, in a stable order for error messages and logs
+    pub fn names(&self) -> Vec<&'static str> {
+        UploaderType::ALL
+            .into_iter()
+            .filter(|uploader_type| self.is_enabled(*uploader_type))
+            .map(|uploader_type| uploader_type.name())
+            .collect()
+    }
+}
+
 // Factory for creating uploaders
 pub struct UploaderFactory;
 
 impl UploaderFactory {
+    /// Creates the uploader, or returns `None` when it isn't configured
     pub async fn create_uploader(
         uploader_type: UploaderType,
         config: &crate::config::AppConfig,
-    ) -> Result<DynImageUploader> {
+    ) -> Result<Option<DynImageUploader>> {
         let uploader: DynImageUploader = match uploader_type {
             UploaderType::Cloudinary => {
-                if !&config.cloudinary.cloud_name.is_empty() {
-                    ()
-                };
+                if !config.cloudinary.is_configured() {
+                    return Ok(None);
+                }
                 Arc::new(CloudinaryUploader::new(
                     &config.cloudinary.cloud_name,
                     &config.cloudinary.api_key,
@@ -55,9 +158,9 @@ impl UploaderFactory {
                 ))
             }
             UploaderType::S3 => {
-                if !&config.s3.access_key.is_empty() {
-                    ()
-                };
+                if !config.s3.is_configured() {
+                    return Ok(None);
+                }
                 Arc::new(
                     S3Uploader::new(
                         &config.s3.access_key,
@@ -69,23 +172,26 @@ impl UploaderFactory {
                 )
             }
             UploaderType::Minio => {
-                if !&config.minio.access_key.is_empty() {
-                    ()
+                if !config.minio.is_configured() {
+                    return Ok(None);
+                }
+                Arc::new(MinioUploader::new(
+                    &config.minio.access_key,
+                    &config.minio.secret_key,
+                    &config.minio.bucket,
+                    &config.minio.endpoint,
+                    &config.minio.region,
+                    config.minio.secure,
+                )?)
+            }
+            UploaderType::Local => {
+                let Some(directory) = &config.local.directory else {
+                    return Ok(None);
                 };
-                Arc::new(
-                    MinioUploader::new(
-                        &config.minio.access_key,
-                        &config.minio.secret_key,
-                        &config.minio.bucket,
-                        &config.minio.endpoint,
-                        &config.minio.region,
-                        config.minio.secure,
-                    )
-                    .await?,
-                )
+                Arc::new(LocalUploader::new(directory, &config.local.public_base_url).await?)
             }
         };
 
-        Ok(uploader)
+        Ok(Some(uploader))
     }
 }
@@ -1,5 +1,5 @@
 // services/upload/s3.rs
-use super::ImageUploader;
+use super::{content_type, ImageUploader, UploadedFile};
 use async_trait::async_trait;
 use aws_config::Region;
 use aws_sdk_s3::{config::Credentials, Client};
@@ -42,7 +42,7 @@ impl ImageUploader for S3Uploader {
         image_data: &[u8],
         format: &str,
         folder: &str,
-    ) -> anyhow::Result<String> {
+    ) -> anyhow::Result<UploadedFile> {
         let key = format!("{}/{}.{}", folder, Uuid::new_v4(), format);
 
         self.client
@@ -50,12 +50,15 @@ impl ImageUploader for S3Uploader {
             .bucket(&self.bucket)
             .key(&key)
             .body(image_data.to_vec().into())
-            .content_type(format!("image/{}", format))
+            .content_type(content_type(format))
             .send()
             .await
             .map_err(|e| anyhow::anyhow!("S3 upload failed: {}", e))?;
 
-        Ok(format!("https://{}.s3.amazonaws.com/{}", self.bucket, key))
+        Ok(UploadedFile {
+            url: format!("https://{}.s3.amazonaws.com/{}", self.bucket, key),
+            id: key,
+        })
     }
 
     async fn delete(&self, file_id: &str) -> anyhow::Result<bool> {